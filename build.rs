@@ -3,6 +3,7 @@ extern crate serde_codegen;
 extern crate serde_json;
 
 use std::env;
+use std::fmt::Write;
 use std::fs;
 use std::path::Path;
 
@@ -11,9 +12,18 @@ fn main() {
     for entry in glob::glob("src/**/*.rs.in").expect("Failed to read glob pattern") {
         println!("cargo:rerun-if-changed={}", entry.unwrap().display());
     }
+    println!("cargo:rerun-if-changed=events.txt");
 
     let out_dir = env::var_os("OUT_DIR").unwrap();
 
+    // The names of the events `Event` models
+    let names = fs::read_to_string("events.txt").expect("Failed to read events.txt");
+    fs::write(
+        Path::new(&out_dir).join("event_names.rs"),
+        event_names(&names),
+    )
+    .expect("Failed to write event_names.rs");
+
     // Switch to our `src` directory so that we have the right base for our
     // globs, and so that we won't need to strip `src/` off every path.
     env::set_current_dir("src").unwrap();
@@ -39,4 +49,437 @@ fn main() {
             }
         }
     }
+
+    // Generate an enum of the known actions of each event from the
+    // documented `action` fields of events.rs.in
+    let events = fs::read_to_string("events.rs.in").expect("Failed to read events.rs.in");
+    fs::write(Path::new(&out_dir).join("actions.rs"), actions(&events))
+        .expect("Failed to write actions.rs");
+
+    // Serialize events the way github sends them, without the variant
+    // name patch_payload_json wraps payloads in to deserialize them
+    let variants = variants(&events);
+    fs::write(
+        Path::new(&out_dir).join("serialize_event.rs"),
+        serialize_event(&variants),
+    )
+    .expect("Failed to write serialize_event.rs");
+
+    // `Event::name`, `Event::action` and the accessors of the fields events
+    // share, from the events of events.txt and the fields of their variants
+    fs::write(
+        Path::new(&out_dir).join("event_accessors.rs"),
+        event_accessors(&names, &variants),
+    )
+    .expect("Failed to write event_accessors.rs");
+}
+
+fn event_names(names: &str) -> String {
+    let mut out = String::from("pub(crate) const EVENTS: &[&str] = &[\n");
+    for name in names.lines().map(str::trim).filter(|name| !name.is_empty()) {
+        writeln!(out, "    \"{}\",", name).unwrap();
+    }
+    out.push_str("];\n");
+    out
+}
+
+/// A field of an `Event` variant
+struct Field {
+    name: String,
+    ty: String,
+    // the name of the field in github's payloads
+    key: String,
+    skip_serializing_if: Option<String>,
+}
+
+/// The variants of the `Event` enum of events.rs.in and their fields
+fn variants(events: &str) -> Vec<(String, Vec<Field>)> {
+    let body = events
+        .split("pub enum Event {")
+        .nth(1)
+        .and_then(|rest| rest.split("\n}\n").next())
+        .expect("Failed to find the Event enum");
+    let mut variants: Vec<(String, Vec<Field>)> = vec![];
+    let (mut key, mut skip_serializing_if) = (None, None);
+    for line in body.lines() {
+        let trimmed = line.trim();
+        if line.starts_with("    ") && !line.starts_with("     ") && trimmed.ends_with(" {") {
+            let name = trimmed.trim_end_matches(" {").to_owned();
+            variants.push((name, vec![]));
+        } else if let Some(rename) = attribute(trimmed, "rename") {
+            key = Some(rename);
+        } else if let Some(skip) = attribute(trimmed, "skip_serializing_if") {
+            skip_serializing_if = Some(skip);
+        } else if let Some(colon) = trimmed.find(": ").filter(|_| !trimmed.starts_with("//")) {
+            let name = trimmed[..colon].to_owned();
+            let fields = &mut variants.last_mut().expect("Field outside of a variant").1;
+            fields.push(Field {
+                key: key.take().unwrap_or_else(|| name.clone()),
+                ty: trimmed[colon + 2..].trim_end_matches(',').to_owned(),
+                name,
+                skip_serializing_if: skip_serializing_if.take(),
+            });
+        }
+    }
+    variants
+}
+
+// whether a variant carries the raw payload of an event it did not parse
+fn is_raw(fields: &[Field]) -> bool {
+    fields.iter().any(|field| field.name == "raw")
+}
+
+/// Generates a `Serialize` implementation for the `Event` enum of events.rs.in
+/// which serializes each variant as a plain struct of its fields, or as its
+/// `raw` payload for variants which were not parsed
+fn serialize_event(variants: &[(String, Vec<Field>)]) -> String {
+    let mut out = String::new();
+    writeln!(out, "impl Serialize for Event {{").unwrap();
+    writeln!(
+        out,
+        "    fn serialize<S>(&self, __serializer: S) -> Result<S::Ok, S::Error>"
+    )
+    .unwrap();
+    writeln!(out, "    where\n        S: Serializer,\n    {{").unwrap();
+    writeln!(out, "        match *self {{").unwrap();
+    for (variant, fields) in variants {
+        if is_raw(fields) {
+            writeln!(
+                out,
+                "            Event::{} {{ ref raw, .. }} => raw.serialize(__serializer),",
+                variant
+            )
+            .unwrap();
+            continue;
+        }
+        writeln!(out, "            Event::{} {{", variant).unwrap();
+        for field in fields {
+            writeln!(out, "                ref {},", field.name).unwrap();
+        }
+        writeln!(out, "            }} => {{").unwrap();
+        let mut len = fields.len().to_string();
+        for field in fields {
+            if let Some(ref skip) = field.skip_serializing_if {
+                write!(len, " - {}({}) as usize", skip, field.name).unwrap();
+            }
+        }
+        writeln!(
+            out,
+            "                let mut __struct = __serializer.serialize_struct(\"{}\", {})?;",
+            variant, len
+        )
+        .unwrap();
+        for field in fields {
+            let serialize = format!(
+                "__struct.serialize_field(\"{}\", {})?;",
+                field.key, field.name
+            );
+            match field.skip_serializing_if {
+                Some(ref skip) => writeln!(
+                    out,
+                    "                if !{}({}) {{\n                    {}\n                }}",
+                    skip, field.name, serialize
+                ),
+                None => writeln!(out, "                {}", serialize),
+            }
+            .unwrap();
+        }
+        writeln!(out, "                __struct.end()\n            }}").unwrap();
+    }
+    writeln!(out, "        }}\n    }}\n}}").unwrap();
+    out
+}
+
+/// Generates `Event::name`, naming each event of events.txt after its
+/// variant, `Event::action`, reading the typed `action` field of
+/// variants which have one and the raw `action` of unparsed variants,
+/// and the accessors of `SHARED_FIELDS`
+fn event_accessors(names: &str, variants: &[(String, Vec<Field>)]) -> String {
+    let raw = variants
+        .iter()
+        .filter(|(_, fields)| is_raw(fields))
+        .map(|(variant, _)| format!("Event::{} {{ ref {{}}, .. }}", variant))
+        .collect::<Vec<_>>();
+    let raw = |field: &str| {
+        raw.iter()
+            .map(|pattern| pattern.replace("{}", field))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    };
+
+    let mut out = String::from("impl Event {\n");
+    writeln!(
+        out,
+        "    /// the name github sends this event under in the `X-Github-Event` header, i.e. `pull_request`"
+    )
+    .unwrap();
+    writeln!(out, "    pub fn name(&self) -> &str {{").unwrap();
+    writeln!(out, "        match *self {{").unwrap();
+    for name in names.lines().map(str::trim).filter(|name| !name.is_empty()) {
+        writeln!(
+            out,
+            "            Event::{} {{ .. }} => \"{}\",",
+            camel(name),
+            name
+        )
+        .unwrap();
+    }
+    writeln!(out, "            {} => name,", raw("name")).unwrap();
+    writeln!(out, "        }}\n    }}\n").unwrap();
+
+    writeln!(
+        out,
+        "    /// the activity which triggered this event, i.e. `opened`,\n    \
+         /// for events which distinguish between activities"
+    )
+    .unwrap();
+    writeln!(out, "    pub fn action(&self) -> Option<&str> {{").unwrap();
+    writeln!(out, "        match *self {{").unwrap();
+    for (variant, fields) in variants {
+        if fields
+            .iter()
+            .any(|field| field.name == "action" && field.ty.ends_with("Action"))
+        {
+            writeln!(
+                out,
+                "            Event::{} {{ ref action, .. }} => Some(action.as_str()),",
+                variant
+            )
+            .unwrap();
+        }
+    }
+    writeln!(
+        out,
+        "            {} => raw.get(\"action\").and_then(|action| action.as_str()),",
+        raw("raw")
+    )
+    .unwrap();
+    writeln!(out, "            _ => None,").unwrap();
+    writeln!(out, "        }}\n    }}").unwrap();
+
+    for &(field, ty, doc) in SHARED_FIELDS {
+        field_accessor(&mut out, variants, field, ty, doc);
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// Fields many events carry, the type their accessor returns a view of
+/// them as and its doc comment
+const SHARED_FIELDS: &[(&str, &str, &str)] = &[
+    (
+        "repository",
+        "RepositoryRef<'_>",
+        "the repository this event concerns, if any",
+    ),
+    (
+        "sender",
+        "AccountRef<'_>",
+        "the account which triggered this event, if any",
+    ),
+    (
+        "organization",
+        "OrganizationRef<'_>",
+        "the organization this event concerns, if any",
+    ),
+    (
+        "installation",
+        "InstallationRef",
+        "the github app installation this event was sent to, if any",
+    ),
+];
+
+/// Generates an accessor of a field events share, converting the field of
+/// every variant which has it, optional or not, into the accessor's view
+fn field_accessor(
+    out: &mut String,
+    variants: &[(String, Vec<Field>)],
+    field: &str,
+    ty: &str,
+    doc: &str,
+) {
+    writeln!(out, "\n    /// {}", doc).unwrap();
+    writeln!(out, "    pub fn {}(&self) -> Option<{}> {{", field, ty).unwrap();
+    writeln!(out, "        match *self {{").unwrap();
+    for (variant, fields) in variants {
+        let optional = match fields.iter().find(|f| f.name == field) {
+            Some(f) => f.ty.starts_with("Option<"),
+            None => continue,
+        };
+        let view = if optional {
+            format!("{}.as_ref().map(Into::into)", field)
+        } else {
+            format!("Some({}.into())", field)
+        };
+        writeln!(
+            out,
+            "            Event::{} {{ ref {}, .. }} => {},",
+            variant, field, view
+        )
+        .unwrap();
+    }
+    writeln!(out, "            _ => None,").unwrap();
+    writeln!(out, "        }}\n    }}").unwrap();
+}
+
+// the value of a `#[serde(name = "value")]` attribute
+fn attribute(line: &str, name: &str) -> Option<String> {
+    line.strip_prefix("#[serde(")
+        .and_then(|attribute| attribute.strip_prefix(name))
+        .and_then(|attribute| attribute.strip_prefix(" = \""))
+        .and_then(|attribute| attribute.strip_suffix("\")]"))
+        .map(str::to_owned)
+}
+
+/// Fields of the form
+///
+/// ```ignore
+/// /// one of `opened`, `closed`
+/// action: PullRequestAction,
+/// ```
+///
+/// are typed with an enum of the actions listed in their doc comment,
+/// which falls back to `Other` for actions github adds later
+fn actions(events: &str) -> String {
+    let mut generated = String::new();
+    let mut documented = vec![];
+    for line in events.lines().map(str::trim) {
+        if let Some(doc) = line.strip_prefix("///") {
+            documented.extend(doc.split('`').skip(1).step_by(2).map(str::to_owned));
+            continue;
+        }
+        let enum_name = line
+            .strip_prefix("action: ")
+            .and_then(|ty| ty.strip_suffix(","))
+            .filter(|ty| ty.ends_with("Action"));
+        if let Some(enum_name) = enum_name {
+            action_enum(&mut generated, enum_name, &documented);
+        }
+        documented.clear();
+    }
+    generated
+}
+
+fn action_enum(out: &mut String, enum_name: &str, actions: &[String]) {
+    let variants = actions
+        .iter()
+        .map(|action| camel(action))
+        .collect::<Vec<_>>();
+    let event = snake(&enum_name[..enum_name.len() - "Action".len()]);
+
+    writeln!(out, "/// Actions of `{}` events", event).unwrap();
+    writeln!(out, "#[derive(Clone, Debug, Eq, Hash, PartialEq)]").unwrap();
+    writeln!(out, "pub enum {} {{", enum_name).unwrap();
+    for (action, variant) in actions.iter().zip(&variants) {
+        writeln!(out, "    /// `{}`", action).unwrap();
+        writeln!(out, "    {},", variant).unwrap();
+    }
+    writeln!(
+        out,
+        "    /// an action this version does not know about yet"
+    )
+    .unwrap();
+    writeln!(out, "    Other(String),").unwrap();
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(out, "impl {} {{", enum_name).unwrap();
+    writeln!(out, "    /// the action as github names it").unwrap();
+    writeln!(out, "    pub fn as_str(&self) -> &str {{").unwrap();
+    writeln!(out, "        match *self {{").unwrap();
+    for (action, variant) in actions.iter().zip(&variants) {
+        writeln!(
+            out,
+            "            {}::{} => \"{}\",",
+            enum_name, variant, action
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "            {}::Other(ref action) => action,",
+        enum_name
+    )
+    .unwrap();
+    writeln!(out, "        }}\n    }}\n}}\n").unwrap();
+
+    writeln!(out, "impl<'a> From<&'a str> for {} {{", enum_name).unwrap();
+    writeln!(out, "    fn from(action: &'a str) -> {} {{", enum_name).unwrap();
+    writeln!(out, "        match action {{").unwrap();
+    for (action, variant) in actions.iter().zip(&variants) {
+        writeln!(
+            out,
+            "            \"{}\" => {}::{},",
+            action, enum_name, variant
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "            other => {}::Other(other.to_owned()),",
+        enum_name
+    )
+    .unwrap();
+    writeln!(out, "        }}\n    }}\n}}\n").unwrap();
+
+    writeln!(out, "impl fmt::Display for {} {{", enum_name).unwrap();
+    writeln!(
+        out,
+        "    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {{"
+    )
+    .unwrap();
+    writeln!(out, "        f.write_str(self.as_str())").unwrap();
+    writeln!(out, "    }}\n}}\n").unwrap();
+
+    writeln!(out, "impl Serialize for {} {{", enum_name).unwrap();
+    writeln!(
+        out,
+        "    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>"
+    )
+    .unwrap();
+    writeln!(out, "    where\n        S: Serializer,\n    {{").unwrap();
+    writeln!(out, "        serializer.serialize_str(self.as_str())").unwrap();
+    writeln!(out, "    }}\n}}\n").unwrap();
+
+    writeln!(out, "impl Deserialize for {} {{", enum_name).unwrap();
+    writeln!(
+        out,
+        "    fn deserialize<D>(deserializer: D) -> Result<{}, D::Error>",
+        enum_name
+    )
+    .unwrap();
+    writeln!(out, "    where\n        D: Deserializer,\n    {{").unwrap();
+    writeln!(
+        out,
+        "        String::deserialize(deserializer).map(|action| {}::from(action.as_str()))",
+        enum_name
+    )
+    .unwrap();
+    writeln!(out, "    }}\n}}\n").unwrap();
+}
+
+// i.e. `review_requested` -> `ReviewRequested`
+fn camel(snake: &str) -> String {
+    snake
+        .split('_')
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .collect()
+}
+
+// i.e. `PullRequest` -> `pull_request`
+fn snake(camel: &str) -> String {
+    let mut snake = String::new();
+    for c in camel.chars() {
+        if c.is_ascii_uppercase() && !snake.is_empty() {
+            snake.push('_')
+        }
+        snake.push(c.to_ascii_lowercase())
+    }
+    snake
 }
@@ -0,0 +1,24 @@
+#[macro_use]
+extern crate log;
+extern crate afterparty_ng;
+extern crate env_logger;
+extern crate futures_util;
+
+use afterparty_ng::server;
+use afterparty_ng::{Blocking, Delivery, Hub};
+use futures_util::future;
+use std::sync::Arc;
+
+pub fn main() {
+    env_logger::init();
+    let mut hub = Hub::new();
+    hub.handle_async("pull_request", |delivery: Arc<Delivery>| {
+        info!("rec delivery {:#?}", delivery);
+        future::ready(())
+    });
+    hub.handle_async(
+        "*",
+        Blocking::new(|delivery: &Delivery| info!("rec {} event", delivery.event)),
+    );
+    server::serve(([0, 0, 0, 0], 4567).into(), hub).unwrap();
+}
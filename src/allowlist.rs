@@ -0,0 +1,429 @@
+//! Restricting deliveries to the address ranges github sends webhooks from
+//!
+//! See github's [meta api](https://developer.github.com/v3/meta/) for the
+//! `hooks` ranges
+
+use serde_json;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Reasons a CIDR range may fail to parse
+#[derive(Debug, PartialEq)]
+pub enum CidrError {
+    /// the network was not a valid ip address
+    InvalidAddress(String),
+    /// the prefix length was not a number or too long for the network
+    InvalidPrefix(String),
+}
+
+impl fmt::Display for CidrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CidrError::InvalidAddress(ref value) => write!(f, "invalid address {:?}", value),
+            CidrError::InvalidPrefix(ref value) => write!(f, "invalid prefix {:?}", value),
+        }
+    }
+}
+
+impl Error for CidrError {}
+
+/// A range of ip addresses, i.e. `192.30.252.0/22`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// parses a range in CIDR notation. Plain addresses are parsed as
+    /// ranges holding only that address
+    pub fn parse(value: &str) -> Result<Cidr, CidrError> {
+        let mut parts = value.trim().splitn(2, '/');
+        let address = parts.next().unwrap_or("");
+        let network =
+            IpAddr::from_str(address).map_err(|_| CidrError::InvalidAddress(address.to_owned()))?;
+        let max = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match parts.next() {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(len) if len <= max => len,
+                _ => return Err(CidrError::InvalidPrefix(prefix.to_owned())),
+            },
+            None => max,
+        };
+        Ok(Cidr { network, prefix })
+    }
+
+    /// returns true if the address is within this range
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.network, canonical(addr)) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = mask(self.prefix, 32) as u32;
+                u32::from(network) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = mask(self.prefix, 128);
+                u128::from(network) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = CidrError;
+
+    fn from_str(value: &str) -> Result<Cidr, CidrError> {
+        Cidr::parse(value)
+    }
+}
+
+// a mask of `prefix` leading ones out of `bits`
+fn mask(prefix: u8, bits: u32) -> u128 {
+    if prefix == 0 {
+        0
+    } else {
+        (!0u128 << (bits - u32::from(prefix))) & (!0u128 >> (128 - bits))
+    }
+}
+
+// ipv4 peers may be reported as ipv4-mapped ipv6 addresses by dual stack sockets
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, hi, lo] => IpAddr::V4(Ipv4Addr::new(
+                (hi >> 8) as u8,
+                hi as u8,
+                (lo >> 8) as u8,
+                lo as u8,
+            )),
+            _ => IpAddr::V6(v6),
+        },
+        v4 => v4,
+    }
+}
+
+/// parses the `hooks` ranges of github's meta api json
+pub fn parse_meta_hooks(json: &str) -> io::Result<Vec<Cidr>> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    let meta =
+        serde_json::from_str::<serde_json::Value>(json).map_err(|e| invalid(e.to_string()))?;
+    let hooks = meta
+        .get("hooks")
+        .and_then(|hooks| hooks.as_array())
+        .ok_or_else(|| invalid("missing hooks ranges".to_owned()))?;
+    hooks
+        .iter()
+        .map(|range| {
+            range
+                .as_str()
+                .ok_or_else(|| invalid(format!("invalid range {}", range)))
+                .and_then(|range| Cidr::parse(range).map_err(|e| invalid(e.to_string())))
+        })
+        .collect()
+}
+
+/// The header a trusted proxy records the addresses it forwards for in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyHeader {
+    /// `X-Forwarded-For`, as appended by i.e. nginx or AWS load balancers
+    XForwardedFor,
+    /// the standard `Forwarded` header
+    Forwarded,
+}
+
+struct MetaFile {
+    path: PathBuf,
+    reload_every: Duration,
+    loaded: Mutex<Instant>,
+}
+
+/// A list of address ranges deliveries are permitted from
+///
+/// Ranges may be loaded from a local copy of github's meta api json,
+/// which is reloaded periodically. When the peer of a request is a
+/// trusted reverse proxy, the client address is taken from the
+/// header that proxy sets instead. Headers a proxy does not set are
+/// never read, as proxies pass them through from clients untouched.
+pub struct Allowlist {
+    ranges: RwLock<Vec<Cidr>>,
+    meta: Option<MetaFile>,
+    trusted_proxies: Vec<(Cidr, ProxyHeader)>,
+}
+
+impl Allowlist {
+    pub fn new(ranges: Vec<Cidr>) -> Allowlist {
+        Allowlist {
+            ranges: RwLock::new(ranges),
+            meta: None,
+            trusted_proxies: vec![],
+        }
+    }
+
+    /// loads the `hooks` ranges from a copy of github's meta api json,
+    /// reloading them when they were last loaded longer ago than `reload_every`
+    pub fn from_meta_file<P>(path: P, reload_every: Duration) -> io::Result<Allowlist>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        let ranges = parse_meta_hooks(&fs::read_to_string(&path)?)?;
+        Ok(Allowlist {
+            meta: Some(MetaFile {
+                path,
+                reload_every,
+                loaded: Mutex::new(Instant::now()),
+            }),
+            ..Allowlist::new(ranges)
+        })
+    }
+
+    /// trusts the `X-Forwarded-For` header of requests from these proxies
+    pub fn trust_proxies(self, proxies: Vec<Cidr>) -> Allowlist {
+        self.trust_proxies_setting(ProxyHeader::XForwardedFor, proxies)
+    }
+
+    /// trusts the given header, and only that header, of requests from these proxies
+    pub fn trust_proxies_setting(mut self, header: ProxyHeader, proxies: Vec<Cidr>) -> Allowlist {
+        self.trusted_proxies
+            .extend(proxies.into_iter().map(|proxy| (proxy, header)));
+        self
+    }
+
+    /// the current list of permitted ranges
+    pub fn ranges(&self) -> Vec<Cidr> {
+        self.reload();
+        match self.ranges.read() {
+            Ok(ranges) => ranges.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    fn reload(&self) {
+        let meta = match self.meta {
+            Some(ref meta) => meta,
+            None => return,
+        };
+        let mut loaded = match meta.loaded.lock() {
+            Ok(loaded) => loaded,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if loaded.elapsed() < meta.reload_every {
+            return;
+        }
+        *loaded = Instant::now();
+        match fs::read_to_string(&meta.path).and_then(|json| parse_meta_hooks(&json)) {
+            Ok(ranges) => {
+                info!("reloaded {} hook ranges from {:?}", ranges.len(), meta.path);
+                match self.ranges.write() {
+                    Ok(mut current) => *current = ranges,
+                    Err(poisoned) => *poisoned.into_inner() = ranges,
+                }
+            }
+            Err(e) => error!("failed to reload hook ranges from {:?}: {}", meta.path, e),
+        }
+    }
+
+    // the header set by the trusted proxy at `addr`, if it is one
+    fn trusted(&self, addr: IpAddr) -> Option<ProxyHeader> {
+        self.trusted_proxies
+            .iter()
+            .find(|&&(proxy, _)| proxy.contains(addr))
+            .map(|&(_, header)| header)
+    }
+
+    /// resolves the address of the client which sent a request, following
+    /// the forwarding header set by trusted proxies. Returns `None` when a
+    /// trusted proxy forwarded a request from an unknown address
+    pub fn client(
+        &self,
+        peer: IpAddr,
+        x_forwarded_for: Option<&str>,
+        forwarded: Option<&str>,
+    ) -> Option<IpAddr> {
+        let header = match self.trusted(peer) {
+            Some(header) => header,
+            None => return Some(peer),
+        };
+        let hops = match (header, x_forwarded_for, forwarded) {
+            (ProxyHeader::XForwardedFor, Some(x_forwarded_for), _) => x_forwarded_for
+                .split(',')
+                .map(|hop| parse_hop(hop.trim()))
+                .collect(),
+            (ProxyHeader::Forwarded, _, Some(forwarded)) => forwarded_for(forwarded),
+            _ => return Some(peer),
+        };
+        // the right most hop was added by the proxy closest to us
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            if self.trusted(client) != Some(header) {
+                break;
+            }
+            match hop {
+                Some(addr) => client = addr,
+                None => return None,
+            }
+        }
+        Some(client)
+    }
+
+    /// returns true if a request from `peer` with the given forwarding
+    /// headers was sent from a permitted address
+    pub fn permits(
+        &self,
+        peer: IpAddr,
+        x_forwarded_for: Option<&str>,
+        forwarded: Option<&str>,
+    ) -> bool {
+        match self.client(peer, x_forwarded_for, forwarded) {
+            Some(client) => self.ranges().iter().any(|range| range.contains(client)),
+            None => false,
+        }
+    }
+}
+
+// extracts the `for` parameters of a `Forwarded` header, elements
+// without one forwarded for an unknown address
+fn forwarded_for(header: &str) -> Vec<Option<IpAddr>> {
+    header
+        .split(',')
+        .map(|element| {
+            element.split(';').find_map(|pair| {
+                let mut kv = pair.trim().splitn(2, '=');
+                match (kv.next(), kv.next()) {
+                    (Some(key), Some(value)) if key.eq_ignore_ascii_case("for") => {
+                        Some(parse_hop(value.trim_matches('"')))
+                    }
+                    _ => None,
+                }
+            })
+        })
+        .map(Option::flatten)
+        .collect()
+}
+
+// parses a forwarded hop, which may carry a port and ipv6 brackets
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    IpAddr::from_str(hop)
+        .ok()
+        .or_else(|| SocketAddr::from_str(hop).ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            Ipv6Addr::from_str(hop.trim_start_matches('[').trim_end_matches(']'))
+                .ok()
+                .map(IpAddr::V6)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_contains() {
+        let v4 = Cidr::parse("192.30.252.0/22").unwrap();
+        assert!(v4.contains(ip("192.30.255.1")));
+        assert!(v4.contains(ip("::ffff:192.30.252.7")));
+        assert!(!v4.contains(ip("192.30.251.255")));
+        let v6 = Cidr::parse("2620:112:3000::/44").unwrap();
+        assert!(v6.contains(ip("2620:112:300f::1")));
+        assert!(!v6.contains(ip("2620:112:3010::1")));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(ip("10.0.0.1")));
+        assert!(Cidr::parse("10.0.0.1").unwrap().contains(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn cidr_errors() {
+        assert_eq!(
+            Err(CidrError::InvalidPrefix("33".to_owned())),
+            Cidr::parse("10.0.0.0/33")
+        );
+        assert_eq!(
+            Err(CidrError::InvalidAddress("github".to_owned())),
+            Cidr::parse("github/8")
+        );
+    }
+
+    #[test]
+    fn reloads_meta_file() {
+        let path = env::temp_dir().join(format!("afterparty-meta-{}.json", ::std::process::id()));
+        fs::write(&path, r#"{"hooks": ["192.30.252.0/22"]}"#).unwrap();
+        let allowlist = Allowlist::from_meta_file(&path, Duration::from_secs(0)).unwrap();
+        assert!(allowlist.permits(ip("192.30.252.1"), None, None));
+        fs::write(&path, r#"{"hooks": ["185.199.108.0/22"]}"#).unwrap();
+        assert!(!allowlist.permits(ip("192.30.252.1"), None, None));
+        assert!(allowlist.permits(ip("185.199.108.1"), None, None));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn follows_trusted_proxies() {
+        let allowlist = Allowlist::new(vec![Cidr::parse("192.30.252.0/22").unwrap()])
+            .trust_proxies(vec![Cidr::parse("10.0.0.0/8").unwrap()]);
+        // untrusted peers may not claim to forward
+        assert!(!allowlist.permits(ip("172.16.0.1"), Some("192.30.252.1"), None));
+        assert!(allowlist.permits(ip("10.0.0.1"), Some("192.30.252.1"), None));
+        assert!(allowlist.permits(
+            ip("10.0.0.1"),
+            Some("1.2.3.4, 192.30.252.1, 10.0.0.2"),
+            None
+        ));
+        assert!(!allowlist.permits(ip("10.0.0.1"), Some("192.30.252.1, 1.2.3.4"), None));
+        assert!(!allowlist.permits(ip("10.0.0.1"), Some("unknown"), None));
+        let allowlist = allowlist.trust_proxies_setting(
+            ProxyHeader::Forwarded,
+            vec![Cidr::parse("172.16.0.0/12").unwrap()],
+        );
+        assert!(allowlist.permits(
+            ip("172.16.0.1"),
+            None,
+            Some(r#"for="192.30.252.1:4711";proto=https"#)
+        ));
+        assert_eq!(
+            Some(ip("2001:db8::1")),
+            allowlist.client(ip("172.16.0.1"), None, Some(r#"For="[2001:db8::1]:4711""#))
+        );
+    }
+
+    #[test]
+    fn ignores_headers_proxies_do_not_set() {
+        let allowlist = Allowlist::new(vec![Cidr::parse("192.30.252.0/22").unwrap()])
+            .trust_proxies(vec![Cidr::parse("10.0.0.0/8").unwrap()])
+            .trust_proxies_setting(
+                ProxyHeader::Forwarded,
+                vec![Cidr::parse("172.16.0.0/12").unwrap()],
+            );
+        // a client's own `Forwarded` header passed through a proxy appending `X-Forwarded-For`
+        let spoofed = Some("for=192.30.252.1");
+        assert!(!allowlist.permits(ip("10.0.0.1"), Some("1.2.3.4"), spoofed));
+        assert!(!allowlist.permits(ip("10.0.0.1"), None, spoofed));
+        assert!(!allowlist.permits(ip("172.16.0.1"), Some("192.30.252.1"), None));
+        // elements without a `for` may not shift hops onto the client's
+        assert_eq!(
+            None,
+            allowlist.client(
+                ip("172.16.0.1"),
+                None,
+                Some("for=192.30.252.1, for=172.16.0.2, proto=https")
+            )
+        );
+        assert!(!allowlist.permits(
+            ip("172.16.0.1"),
+            None,
+            Some("for=192.30.252.1, for=172.16.0.2, proto=https")
+        ));
+    }
+}
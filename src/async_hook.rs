@@ -0,0 +1,264 @@
+//! Hooks which handle deliveries asynchronously
+//!
+//! Async hooks return a future rather than handling a delivery on the
+//! thread it was received on, so slow hooks do not tie up connections
+
+use super::{Delivery, Hook, HookOutcome, Hub};
+use dispatch::{self, DispatchOutcome, HeaderSource};
+use futures_util::future::{self, Either};
+use futures_util::FutureExt;
+use std::future::Future;
+use std::net::IpAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::thread;
+use tokio::task;
+
+/// The future an `AsyncHook` returns
+pub type HookFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// Handles webhook deliveries asynchronously
+pub trait AsyncHook: Send + Sync {
+    /// Implementations are expected to handle deliveries in the returned future
+    fn handle(&self, delivery: Arc<Delivery>) -> HookFuture;
+}
+
+impl<F, R> AsyncHook for F
+where
+    F: Fn(Arc<Delivery>) -> R,
+    F: Sync + Send,
+    R: Future<Output = ()> + Send + 'static,
+{
+    fn handle(&self, delivery: Arc<Delivery>) -> HookFuture {
+        Box::pin(self(delivery))
+    }
+}
+
+/// Adapts a synchronous hook to an async one by running it on
+/// tokio's blocking thread pool
+pub struct Blocking<H: Hook + 'static> {
+    hook: Arc<H>,
+}
+
+impl<H: Hook + 'static> Blocking<H> {
+    pub fn new(hook: H) -> Blocking<H> {
+        Blocking {
+            hook: Arc::new(hook),
+        }
+    }
+}
+
+impl<H: Hook + 'static> AsyncHook for Blocking<H> {
+    fn handle(&self, delivery: Arc<Delivery>) -> HookFuture {
+        let hook = self.hook.clone();
+        Box::pin(
+            task::spawn_blocking(move || hook.handle(&delivery)).map(|result| {
+                if let Err(e) = result {
+                    // surface the hook's panic to whoever awaits it
+                    if e.is_panic() {
+                        panic::resume_unwind(e.into_panic())
+                    }
+                }
+            }),
+        )
+    }
+}
+
+impl Hub {
+    /// verifies, parses and dispatches a delivery sent from `peer`, if known,
+    /// to interested hooks, resolving to the response to send to github
+    ///
+    /// Synchronous hooks are run on tokio's blocking thread pool, after which
+    /// async hooks are run concurrently. As with `dispatch`, deliveries from
+    /// unknown peers are rejected when the hub has an allowlist. The returned
+    /// future must be polled within a tokio runtime
+    pub fn dispatch_async<H, B>(
+        self: Arc<Self>,
+        peer: Option<IpAddr>,
+        headers: H,
+        body: B,
+    ) -> impl Future<Output = DispatchOutcome> + Send
+    where
+        H: HeaderSource + Send + 'static,
+        B: AsRef<[u8]> + Send + 'static,
+    {
+        let hub = self.clone();
+        future::lazy(move |_| {
+            task::spawn_blocking(move || hub.process_blocking(peer, &headers, body.as_ref()))
+        })
+        .flatten()
+        .then(move |processed| match processed {
+            Ok(Ok((delivery, mut results))) => {
+                let id = delivery.id.clone();
+                Either::Left(self.run_async(delivery).map(move |async_results| {
+                    results.extend(async_results);
+                    self.settle(&id, results)
+                }))
+            }
+            Ok(Err(outcome)) => Either::Right(future::ready(outcome)),
+            Err(e) => {
+                error!("failed to dispatch delivery: {}", e);
+                self.metrics.count_failed();
+                Either::Right(future::ready(DispatchOutcome::new(500, "hook failed")))
+            }
+        })
+    }
+
+    /// the blocking part of `dispatch_async`, responding early unless
+    /// async hooks remain to be run
+    fn process_blocking<H>(
+        &self,
+        peer: Option<IpAddr>,
+        headers: &H,
+        body: &[u8],
+    ) -> Result<(Arc<Delivery>, dispatch::Results), DispatchOutcome>
+    where
+        H: HeaderSource + ?Sized,
+    {
+        let request = self.admit(peer, headers, body)?;
+        let (hooks, async_hooks) = match (
+            self.subscribed_hooks(&request.event, request.payload),
+            self.subscribed_async_hooks(&request.event, request.payload),
+        ) {
+            (Ok(hooks), Ok(async_hooks)) => (hooks, async_hooks),
+            (Err(e), _) | (_, Err(e)) => return Err(self.unparseable(&request, e)),
+        };
+        if hooks.is_none() && async_hooks.is_none() {
+            return Err(self.unsubscribed(&request));
+        }
+        let delivery = self.parse(request)?;
+        let results = dispatch::run(hooks.unwrap_or_default(), &delivery);
+        Ok((Arc::new(delivery), results))
+    }
+
+    fn run_async(&self, delivery: Arc<Delivery>) -> impl Future<Output = dispatch::Results> + Send {
+        let hooks = self
+            .subscribed_async_hooks(&delivery.event, &delivery.unparsed_payload)
+            .ok()
+            .and_then(|hooks| hooks)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|hook| {
+                let (name, required) = (hook.name().to_owned(), hook.required());
+                let id = delivery.id.clone();
+                let report = move |result: thread::Result<()>| {
+                    let result = result.map(|()| Ok(HookOutcome::Handled));
+                    (
+                        required,
+                        dispatch::hook_result(&name, required, &id, result),
+                    )
+                };
+                match panic::catch_unwind(AssertUnwindSafe(|| hook.hook().handle(delivery.clone())))
+                {
+                    Ok(handled) => AssertUnwindSafe(handled).catch_unwind().map(report).boxed(),
+                    Err(panic) => future::ready(report(Err(panic))).boxed(),
+                }
+            })
+            .collect::<Vec<_>>();
+        future::join_all(hooks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dispatch::HookResult;
+    use http::HeaderMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::runtime::Runtime;
+
+    const WATCH: &str = include_str!("../data/watch.json");
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-GitHub-Event", "watch".parse().unwrap());
+        headers.insert(
+            "X-GitHub-Delivery",
+            "72d3162e-cc78-11e3-81ab-4c9367dc0958".parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn header_map_source() {
+        let mut headers = headers();
+        headers.append("Forwarded", "a".parse().unwrap());
+        headers.append("Forwarded", "b".parse().unwrap());
+        assert_eq!(Some("watch".to_owned()), headers.header("x-github-event"));
+        assert_eq!(Some("a,b".to_owned()), headers.header("Forwarded"));
+        assert_eq!(None, headers.header("X-Hub-Signature"));
+    }
+
+    #[test]
+    fn dispatch_to_async_and_blocking_hooks() {
+        let dispatched = Arc::new(AtomicUsize::new(0));
+        let mut hub = Hub::new();
+        let counter = dispatched.clone();
+        hub.handle_async("watch", move |delivery: Arc<Delivery>| {
+            assert_eq!("watch", delivery.event);
+            counter.fetch_add(1, Ordering::SeqCst);
+            future::ready(())
+        });
+        let counter = dispatched.clone();
+        hub.handle_async(
+            "*",
+            Blocking::new(move |_: &Delivery| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }),
+        );
+        let counter = dispatched.clone();
+        hub.handle("watch", move |_: &Delivery| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let hub = Arc::new(hub);
+        let outcome =
+            Runtime::new()
+                .unwrap()
+                .block_on(hub.clone().dispatch_async(None, headers(), WATCH));
+        assert_eq!(202, outcome.status);
+        assert_eq!(vec![HookResult::Completed; 3], outcome.hooks);
+        assert_eq!(3, dispatched.load(Ordering::SeqCst));
+        assert_eq!(1, hub.metrics().accepted())
+    }
+
+    #[test]
+    fn dispatch_catches_panicking_async_hooks() {
+        let mut hub = Hub::new();
+        hub.handle_async("watch", |_: Arc<Delivery>| -> future::Ready<()> {
+            panic!("boom")
+        });
+        hub.handle_async(
+            "watch",
+            Blocking::new(|_: &Delivery| panic!("blocking boom")),
+        );
+        hub.handle_async("watch", |_: Arc<Delivery>| {
+            future::lazy(|_| panic!("later"))
+        });
+        let outcome = Runtime::new()
+            .unwrap()
+            .block_on(Arc::new(hub).dispatch_async(None, headers(), WATCH));
+        assert_eq!(500, outcome.status);
+        assert_eq!(
+            vec![
+                HookResult::Panicked("boom".to_owned()),
+                HookResult::Panicked("blocking boom".to_owned()),
+                HookResult::Panicked("later".to_owned()),
+            ],
+            outcome.hooks
+        )
+    }
+
+    #[test]
+    fn dispatch_async_rejects_bad_requests() {
+        let mut hub = Hub::new();
+        hub.handle_async("watch", |_: Arc<Delivery>| future::ready(()));
+        let hub = Arc::new(hub);
+        let runtime = Runtime::new().unwrap();
+        let outcome = runtime.block_on(hub.clone().dispatch_async(None, HeaderMap::new(), WATCH));
+        assert_eq!(400, outcome.status);
+        let outcome = runtime.block_on(hub.clone().dispatch_async(None, headers(), "{"));
+        assert_eq!(400, outcome.status);
+        assert_eq!(2, hub.metrics().rejected())
+    }
+}
@@ -0,0 +1,581 @@
+//! Transport independent dispatch of deliveries
+//!
+//! `Hub::dispatch` verifies, parses and dispatches a delivery given only its
+//! headers and body so that hubs may be mounted into any http stack
+
+use super::{Delivery, Hub};
+use error::DeliveryError;
+use events::{self, Event};
+use hook::{FallibleHook, HookError, HookOutcome, Registered};
+use hyper::header::Headers;
+use ids::DeliveryId;
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::net::IpAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::str;
+use std::thread;
+
+/// A collection of request headers a hub may look up delivery headers in
+pub trait HeaderSource {
+    /// returns the value of the named header, matched case insensitively.
+    /// Headers sent more than once are joined with `,`
+    fn header(&self, name: &str) -> Option<String>;
+}
+
+impl HeaderSource for Headers {
+    fn header(&self, name: &str) -> Option<String> {
+        self.get_raw(name).map(|values| {
+            values
+                .iter()
+                .map(|value| String::from_utf8_lossy(value).into_owned())
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+    }
+}
+
+impl<K, V> HeaderSource for [(K, V)]
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    fn header(&self, name: &str) -> Option<String> {
+        let values = self
+            .iter()
+            .filter(|(key, _)| key.as_ref().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_ref())
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(","))
+        }
+    }
+}
+
+impl<K, V> HeaderSource for Vec<(K, V)>
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    fn header(&self, name: &str) -> Option<String> {
+        self[..].header(name)
+    }
+}
+
+impl<K, V, S> HeaderSource for HashMap<K, V, S>
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+    S: BuildHasher,
+{
+    fn header(&self, name: &str) -> Option<String> {
+        self.iter()
+            .find(|&(key, _)| key.as_ref().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_ref().to_owned())
+    }
+}
+
+#[cfg(feature = "async")]
+impl HeaderSource for ::http::HeaderMap {
+    fn header(&self, name: &str) -> Option<String> {
+        let values = self
+            .get_all(name)
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(","))
+        }
+    }
+}
+
+/// The result of dispatching a delivery to a single hook
+#[derive(Clone, Debug, PartialEq)]
+pub enum HookResult {
+    /// the hook handled the delivery
+    Completed,
+    /// the hook decided the delivery was not of interest
+    Skipped,
+    /// the hook refused the delivery, i.e. because it failed to authenticate
+    Rejected,
+    /// the hook failed to handle the delivery
+    Failed(String),
+    /// the hook panicked while handling the delivery
+    Panicked(String),
+}
+
+impl HookResult {
+    /// whether the hook failed or panicked
+    pub fn failed(&self) -> bool {
+        matches!(*self, HookResult::Failed(_) | HookResult::Panicked(_))
+    }
+}
+
+/// How a hub responded to a delivery
+#[derive(Clone, Debug, PartialEq)]
+pub struct DispatchOutcome {
+    /// the http status to respond to github with
+    pub status: u16,
+    /// the body to respond to github with
+    pub body: String,
+    /// the result of each hook the delivery was dispatched to, in dispatch order
+    pub hooks: Vec<HookResult>,
+}
+
+impl DispatchOutcome {
+    pub(crate) fn new<B>(status: u16, body: B) -> DispatchOutcome
+    where
+        B: Into<String>,
+    {
+        DispatchOutcome {
+            status,
+            body: body.into(),
+            hooks: vec![],
+        }
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(message) => (*message).to_owned(),
+            Err(_) => "unknown panic".to_owned(),
+        },
+    }
+}
+
+impl Hub {
+    /// verifies, parses and dispatches a delivery to interested hooks,
+    /// returning the response to send to github
+    ///
+    /// The address of the request's peer is unknown to this method so
+    /// deliveries are rejected when the hub has an allowlist. Use
+    /// `dispatch_from` instead when the peer is known.
+    pub fn dispatch<H>(&self, headers: &H, body: &[u8]) -> DispatchOutcome
+    where
+        H: HeaderSource + ?Sized,
+    {
+        self.process(None, headers, body)
+    }
+
+    /// verifies, parses and dispatches a delivery sent from `peer`
+    /// to interested hooks, returning the response to send to github
+    pub fn dispatch_from<H>(&self, peer: IpAddr, headers: &H, body: &[u8]) -> DispatchOutcome
+    where
+        H: HeaderSource + ?Sized,
+    {
+        self.process(Some(peer), headers, body)
+    }
+
+    fn process<H>(&self, peer: Option<IpAddr>, headers: &H, body: &[u8]) -> DispatchOutcome
+    where
+        H: HeaderSource + ?Sized,
+    {
+        let request = match self.admit(peer, headers, body) {
+            Ok(request) => request,
+            Err(outcome) => return outcome,
+        };
+        let hooks = match self.subscribed_hooks(&request.event, request.payload) {
+            Ok(Some(hooks)) => hooks,
+            Ok(None) => return self.unsubscribed(&request),
+            Err(e) => return self.unparseable(&request, e),
+        };
+        let delivery = match self.parse(request) {
+            Ok(delivery) => delivery,
+            Err(outcome) => return outcome,
+        };
+        self.settle(&delivery.id, run(hooks, &delivery))
+    }
+
+    /// checks a request's origin, headers and signature
+    pub(crate) fn admit<'a, H>(
+        &self,
+        peer: Option<IpAddr>,
+        headers: &H,
+        body: &'a [u8],
+    ) -> Result<Admitted<'a>, DispatchOutcome>
+    where
+        H: HeaderSource + ?Sized,
+    {
+        self.metrics.count_received();
+        if let Some(ref allowlist) = self.allowlist {
+            let x_forwarded_for = headers.header("X-Forwarded-For");
+            let forwarded = headers.header("Forwarded");
+            let permitted = peer
+                .map(|peer| {
+                    allowlist.permits(
+                        peer,
+                        x_forwarded_for.as_ref().map(|h| h.as_ref()),
+                        forwarded.as_ref().map(|h| h.as_ref()),
+                    )
+                })
+                .unwrap_or(false);
+            if !permitted {
+                warn!(
+                    "rejecting request from {:?} (x-forwarded-for {:?}, forwarded {:?})",
+                    peer, x_forwarded_for, forwarded
+                );
+                return Err(self.reject(403, "forbidden"));
+            }
+        }
+        let (event, delivery) = match (
+            headers.header("X-Github-Event"),
+            headers.header("X-Github-Delivery"),
+        ) {
+            (Some(event), Some(delivery)) => (event, delivery),
+            _ => return Err(self.reject(400, "missing X-Github-Event or X-Github-Delivery header")),
+        };
+        let delivery = match delivery.parse::<DeliveryId>() {
+            Ok(delivery) => delivery,
+            Err(e) => {
+                // not reported, the request is not authenticated yet
+                warn!("rejecting request: {}", e);
+                return Err(self.reject(400, "invalid X-Github-Delivery header"));
+            }
+        };
+        let signature = headers.header("X-Hub-Signature");
+        let signature_256 = headers.header("X-Hub-Signature-256");
+        info!(
+            "recv '{}' event with signature '{:?}' and sha256 signature '{:?}'",
+            event, signature, signature_256
+        );
+        let payload = match str::from_utf8(body) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("failed to read payload for delivery {}: {}", delivery, e);
+                return Err(self.reject(400, "unreadable payload"));
+            }
+        };
+        if let Some(ref verifier) = self.verifier {
+            match verifier.verify(
+                payload,
+                signature.as_ref().map(|s| s.as_ref()),
+                signature_256.as_ref().map(|s| s.as_ref()),
+            ) {
+                Ok(name) => info!("authenticated delivery {} with secret '{}'", delivery, name),
+                Err(e) => {
+                    // the reason stays in the log, unauthenticated callers
+                    // could otherwise probe which secrets are configured
+                    error!("failed to authenticate delivery {}: {}", delivery, e);
+                    return Err(self.reject(401, "unauthorized"));
+                }
+            }
+        }
+        Ok(Admitted {
+            event,
+            delivery,
+            payload,
+            signature,
+            signature_256,
+        })
+    }
+
+    /// parses an admitted request, skipping deliveries seen before
+    pub(crate) fn parse(&self, request: Admitted) -> Result<Delivery, DispatchOutcome> {
+        let parsed = match events::parse_raw(&request.event, request.payload) {
+            Ok(Ok(parsed)) => parsed,
+            Ok(Err((e, raw))) => {
+                warn!("failed to parse delivery {}: {}", request.delivery, e);
+                let unparsed = Event::unparsed(e.clone(), raw);
+                self.report(&DeliveryError::Mismatch(e));
+                unparsed
+            }
+            Err(e) => return Err(self.unparseable(&request, e)),
+        };
+        let delivery = Delivery::with_payload(
+            request.delivery,
+            &request.event,
+            parsed,
+            request.payload,
+            request.signature.as_ref().map(|s| s.as_ref()),
+            request.signature_256.as_ref().map(|s| s.as_ref()),
+        );
+        if let Some(ref store) = self.deliveries {
+            if !store.record(&delivery.id.to_string()) {
+                warn!("skipping duplicate delivery {}", delivery.id);
+                self.metrics.count_duplicate();
+                return Err(DispatchOutcome::new(200, "duplicate"));
+            }
+        }
+        Ok(delivery)
+    }
+
+    /// acknowledges an admitted request no hook subscribed to without
+    /// parsing it, once its payload is known to be json
+    pub(crate) fn unsubscribed(&self, request: &Admitted) -> DispatchOutcome {
+        match events::parse_action(&request.event, request.payload) {
+            Ok(_) => {
+                self.metrics.count_accepted();
+                DispatchOutcome::new(202, "accepted")
+            }
+            Err(e) => self.unparseable(request, e),
+        }
+    }
+
+    /// rejects an admitted request whose payload could not be parsed
+    pub(crate) fn unparseable(&self, request: &Admitted, error: DeliveryError) -> DispatchOutcome {
+        error!("failed to parse delivery {}: {}", request.delivery, error);
+        self.report(&error);
+        self.reject(400, "unparseable payload")
+    }
+
+    /// refuses a request, counting it as rejected
+    fn reject(&self, status: u16, body: &str) -> DispatchOutcome {
+        self.metrics.count_rejected();
+        DispatchOutcome::new(status, body)
+    }
+
+    /// responds to a delivery according to the results of its hooks.
+    /// Deliveries every hook skipped or rejected, and at least one rejected,
+    /// are unauthorized. The id of a delivery hooks failed to handle is
+    /// forgotten, so that github's redelivery of it is dispatched again, as
+    /// is that of a rejected one, so that a forged delivery does not hold on
+    /// to its id
+    pub(crate) fn settle(&self, id: &DeliveryId, results: Results) -> DispatchOutcome {
+        let failed = results
+            .iter()
+            .any(|&(required, ref result)| required && result.failed());
+        let rejected = results
+            .iter()
+            .any(|(_, result)| *result == HookResult::Rejected)
+            && results
+                .iter()
+                .all(|(_, result)| matches!(*result, HookResult::Rejected | HookResult::Skipped));
+        let outcome = if failed {
+            self.metrics.count_failed();
+            DispatchOutcome::new(500, "hook failed")
+        } else if rejected {
+            self.metrics.count_rejected();
+            DispatchOutcome::new(401, "unauthorized")
+        } else {
+            self.metrics.count_accepted();
+            DispatchOutcome::new(202, "accepted")
+        };
+        if failed || rejected {
+            if let Some(ref store) = self.deliveries {
+                store.forget(&id.to_string())
+            }
+        }
+        DispatchOutcome {
+            hooks: results.into_iter().map(|(_, result)| result).collect(),
+            ..outcome
+        }
+    }
+
+    /// passes the error of a delivery which failed to parse on to error hooks
+    fn report(&self, error: &DeliveryError) {
+        for hook in &self.errors {
+            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| hook(error))) {
+                error!("error hook panicked: {}", panic_message(panic))
+            }
+        }
+    }
+}
+
+/// A request which passed the hub's checks but was not yet parsed
+pub(crate) struct Admitted<'a> {
+    pub(crate) event: String,
+    delivery: DeliveryId,
+    pub(crate) payload: &'a str,
+    signature: Option<String>,
+    signature_256: Option<String>,
+}
+
+/// the results of hooks, each paired with whether the hook was required
+pub(crate) type Results = Vec<(bool, HookResult)>;
+
+pub(crate) fn run<'a, I>(hooks: I, delivery: &Delivery) -> Results
+where
+    I: IntoIterator<Item = &'a Registered<dyn FallibleHook>>,
+{
+    hooks
+        .into_iter()
+        .map(|hook| {
+            let result = hook_result(
+                hook.name(),
+                hook.required(),
+                &delivery.id,
+                panic::catch_unwind(AssertUnwindSafe(|| hook.hook().handle(delivery))),
+            );
+            (hook.required(), result)
+        })
+        .collect()
+}
+
+/// logs what a hook did with a delivery under the hook's name
+pub(crate) fn hook_result(
+    name: &str,
+    required: bool,
+    id: &DeliveryId,
+    result: thread::Result<Result<HookOutcome, HookError>>,
+) -> HookResult {
+    match result {
+        Ok(Ok(HookOutcome::Handled)) => {
+            info!("hook '{}' handled delivery {}", name, id);
+            HookResult::Completed
+        }
+        Ok(Ok(HookOutcome::Skipped)) => {
+            info!("hook '{}' skipped delivery {}", name, id);
+            HookResult::Skipped
+        }
+        Ok(Ok(HookOutcome::Rejected)) => {
+            warn!("hook '{}' rejected delivery {}", name, id);
+            HookResult::Rejected
+        }
+        Ok(Err(e)) => {
+            if required {
+                error!("hook '{}' failed handling delivery {}: {}", name, id, e)
+            } else {
+                warn!(
+                    "optional hook '{}' failed handling delivery {}: {}",
+                    name, id, e
+                )
+            }
+            HookResult::Failed(e.to_string())
+        }
+        Err(panic) => {
+            let message = panic_message(panic);
+            error!(
+                "hook '{}' panicked handling delivery {}: {}",
+                name, id, message
+            );
+            HookResult::Panicked(message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const WATCH: &str = include_str!("../data/watch.json");
+
+    #[test]
+    fn header_sources() {
+        let pairs = vec![
+            ("x-github-event", "push"),
+            ("Forwarded", "a"),
+            ("forwarded", "b"),
+        ];
+        assert_eq!(Some("push".to_owned()), pairs.header("X-Github-Event"));
+        assert_eq!(Some("a,b".to_owned()), pairs.header("Forwarded"));
+        assert_eq!(None, pairs.header("X-Github-Delivery"));
+        let mut map = HashMap::new();
+        map.insert("X-GitHub-Event".to_owned(), "push".to_owned());
+        assert_eq!(Some("push".to_owned()), map.header("x-github-event"));
+    }
+
+    #[test]
+    fn reject_unparseable_payloads_without_hooks() {
+        let hub = Hub::new();
+        let headers = [
+            ("X-GitHub-Event", "watch"),
+            ("X-GitHub-Delivery", "72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+        ];
+        assert_eq!(202, hub.dispatch(&headers[..], WATCH.as_bytes()).status);
+        let outcome = hub.dispatch(&headers[..], b"{\"action\": ");
+        assert_eq!(400, outcome.status);
+        assert_eq!("unparseable payload", outcome.body);
+        assert_eq!(2, hub.metrics().received());
+        assert_eq!(1, hub.metrics().accepted());
+        assert_eq!(1, hub.metrics().rejected())
+    }
+
+    #[test]
+    fn dispatch_with_plain_headers() {
+        let mut hub = Hub::new();
+        hub.handle("watch", |_: &Delivery| {});
+        let headers = [
+            ("X-GitHub-Event", "watch"),
+            ("X-GitHub-Delivery", "72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+        ];
+        let outcome = hub.dispatch(&headers[..], WATCH.as_bytes());
+        assert_eq!(202, outcome.status);
+        assert_eq!(vec![HookResult::Completed], outcome.hooks);
+        assert_eq!(400, hub.dispatch(&headers[..], b"\xff").status);
+        let headers = [("X-GitHub-Event", "watch"), ("X-GitHub-Delivery", "1")];
+        assert_eq!(202, hub.dispatch(&headers[..], WATCH.as_bytes()).status);
+        let headers = [("X-GitHub-Event", "watch"), ("X-GitHub-Delivery", "")];
+        let outcome = hub.dispatch(&headers[..], WATCH.as_bytes());
+        assert_eq!(400, outcome.status);
+        assert_eq!("invalid X-Github-Delivery header", outcome.body);
+        assert_eq!(4, hub.metrics().received());
+        assert_eq!(2, hub.metrics().accepted());
+        assert_eq!(2, hub.metrics().rejected())
+    }
+
+    #[test]
+    fn dispatch_catches_panicking_hooks() {
+        let mut hub = Hub::new();
+        hub.handle("watch", |_: &Delivery| panic!("boom"));
+        hub.handle("watch", |_: &Delivery| {});
+        let headers = vec![
+            ("X-GitHub-Event", "watch"),
+            ("X-GitHub-Delivery", "72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+        ];
+        let outcome = hub.dispatch(&headers, WATCH.as_bytes());
+        assert_eq!(500, outcome.status);
+        assert_eq!(
+            vec![
+                HookResult::Panicked("boom".to_owned()),
+                HookResult::Completed
+            ],
+            outcome.hooks
+        );
+        assert_eq!(1, hub.metrics().failed())
+    }
+
+    #[test]
+    fn dispatch_reflects_fallible_hooks() {
+        let headers = [
+            ("X-GitHub-Event", "watch"),
+            ("X-GitHub-Delivery", "72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+        ];
+        let mut hub = Hub::new();
+        hub.handle_fallible("watch", "star", |_: &Delivery| Ok(HookOutcome::Handled));
+        hub.handle_fallible("watch", "ignore", |_: &Delivery| Ok(HookOutcome::Skipped));
+        hub.handle_optional("watch", "notify", |_: &Delivery| Err("unreachable".into()));
+        let outcome = hub.dispatch(&headers[..], WATCH.as_bytes());
+        assert_eq!(202, outcome.status);
+        assert_eq!(
+            vec![
+                HookResult::Completed,
+                HookResult::Skipped,
+                HookResult::Failed("unreachable".to_owned())
+            ],
+            outcome.hooks
+        );
+        hub.handle_fallible("watch", "deploy", |_: &Delivery| {
+            Err(HookError::new("deploy failed"))
+        });
+        let outcome = hub.dispatch(&headers[..], WATCH.as_bytes());
+        assert_eq!(500, outcome.status);
+        assert_eq!(
+            Some(HookResult::Failed("deploy failed".to_owned())),
+            outcome.hooks.last().cloned()
+        );
+    }
+
+    #[test]
+    fn hooks_are_named() {
+        let mut hub = Hub::new();
+        hub.handle("watch", |_: &Delivery| {});
+        hub.handle_optional("watch", "notify", |_: &Delivery| Ok(HookOutcome::Handled));
+        hub.handle("*", |_: &Delivery| {});
+        let hooks = hub.hooks("watch").unwrap();
+        assert_eq!(
+            vec![("watch#0", true), ("notify", false), ("*#0", true)],
+            hooks
+                .iter()
+                .map(|hook| (hook.name(), hook.required()))
+                .collect::<Vec<_>>()
+        );
+    }
+}
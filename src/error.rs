@@ -0,0 +1,333 @@
+//! Errors parsing deliveries
+//!
+//! Github adds and changes fields of payloads over time. When a payload no
+//! longer matches the model of its event the error names the json path of
+//! the field which did not match, what was expected there and what was found
+
+use ids::DeliveryIdError;
+use serde_json::{self, Value};
+use std::error::Error;
+use std::fmt;
+
+/// Reasons a delivery may fail to parse
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeliveryError {
+    /// the delivery id is empty, too long or holds other characters
+    /// than printable ascii without whitespace
+    InvalidId(DeliveryIdError),
+    /// the payload is not json
+    InvalidJson { event: String, message: String },
+    /// the payload does not match the model of its event
+    Mismatch(PayloadError),
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeliveryError::InvalidId(ref e) => write!(f, "{}", e),
+            DeliveryError::InvalidJson {
+                ref event,
+                ref message,
+            } => write!(f, "invalid json in {} payload: {}", event, message),
+            DeliveryError::Mismatch(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for DeliveryError {}
+
+impl From<DeliveryIdError> for DeliveryError {
+    fn from(error: DeliveryIdError) -> DeliveryError {
+        DeliveryError::InvalidId(error)
+    }
+}
+
+/// A payload which does not match the model of its event
+#[derive(Clone, Debug, PartialEq)]
+pub struct PayloadError {
+    /// the event the payload was sent for, i.e. `pull_request`
+    pub event: String,
+    /// the json path of the field which did not match, i.e. `pull_request.labels[0].name`,
+    /// empty when the payload itself did not match
+    pub path: String,
+    /// what the model expected, i.e. `i64` or ``field `name` ``
+    pub expected: String,
+    /// the json found at the path, `nothing` for missing fields
+    pub found: String,
+    /// serde's description of the error
+    pub message: String,
+}
+
+impl PayloadError {
+    /// locates the field which did not match in a payload, `offset` being
+    /// the number of bytes of the payload serde read before it failed
+    pub(crate) fn new(
+        event: &str,
+        payload: &str,
+        raw: &Value,
+        offset: usize,
+        error: &serde_json::Error,
+    ) -> PayloadError {
+        let mut message = error.to_string();
+        let position = format!(" at line {} column {}", error.line(), error.column());
+        if message.ends_with(&position) {
+            let len = message.len() - position.len();
+            message.truncate(len)
+        }
+        let mut path = if error.line() == 0 {
+            vec![]
+        } else {
+            locate(&payload[..floor_char_boundary(payload, offset)])
+        };
+        let (expected, found) = match missing_field(&message) {
+            Some(field) => {
+                path.push(Segment::Key(field.to_owned()));
+                (format!("field `{}`", field), "nothing".to_owned())
+            }
+            None => (
+                message
+                    .find(", expected ")
+                    .map(|at| message[at + ", expected ".len()..].to_owned())
+                    .unwrap_or_else(|| message.clone()),
+                lookup(raw, &path)
+                    .map(describe)
+                    .unwrap_or_else(|| "nothing".to_owned()),
+            ),
+        };
+        PayloadError {
+            event: event.to_owned(),
+            path: render(&path),
+            expected,
+            found,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} payload does not match at {}: expected {}, found {}",
+            self.event,
+            if self.path.is_empty() {
+                "its root"
+            } else {
+                &self.path
+            },
+            self.expected,
+            self.found
+        )
+    }
+}
+
+impl Error for PayloadError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+// an object or array the payload was read up to, and the member being read
+struct Frame {
+    object: bool,
+    key: Option<String>,
+    index: usize,
+}
+
+fn path_of(frames: &[Frame]) -> Vec<Segment> {
+    frames
+        .iter()
+        .filter_map(|frame| {
+            if frame.object {
+                frame.key.clone().map(Segment::Key)
+            } else {
+                Some(Segment::Index(frame.index))
+            }
+        })
+        .collect()
+}
+
+/// the path of the value serde was reading when it stopped after `read`,
+/// which is the value it read last or, when it stopped before reading
+/// one, the value it was about to read
+fn locate(read: &str) -> Vec<Segment> {
+    let mut frames: Vec<Frame> = vec![];
+    let mut last = None;
+    let mut chars = read.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '[' => {
+                frames.push(Frame {
+                    object: c == '{',
+                    key: None,
+                    index: 0,
+                });
+                last = None
+            }
+            '}' | ']' => {
+                frames.pop();
+                last = Some(path_of(&frames))
+            }
+            ':' => last = None,
+            ',' => {
+                if let Some(frame) = frames.last_mut() {
+                    if frame.object {
+                        frame.key = None
+                    } else {
+                        frame.index += 1
+                    }
+                }
+                last = None
+            }
+            '"' => {
+                let mut string = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => {
+                            if let Some(escaped) = chars.next() {
+                                string.push(escaped)
+                            }
+                        }
+                        c => string.push(c),
+                    }
+                }
+                match frames.last_mut() {
+                    Some(ref mut frame) if frame.object && frame.key.is_none() => {
+                        frame.key = Some(string)
+                    }
+                    _ => last = Some(path_of(&frames)),
+                }
+            }
+            c if c.is_whitespace() => (),
+            _ => {
+                while let Some(&c) = chars.peek() {
+                    if c == ',' || c == '}' || c == ']' || c.is_whitespace() {
+                        break;
+                    }
+                    chars.next();
+                }
+                last = Some(path_of(&frames))
+            }
+        }
+    }
+    last.unwrap_or_else(|| path_of(&frames))
+}
+
+fn floor_char_boundary(text: &str, offset: usize) -> usize {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1
+    }
+    offset
+}
+
+fn missing_field(message: &str) -> Option<&str> {
+    if message.starts_with("missing field `") && message.ends_with('`') {
+        Some(&message["missing field `".len()..message.len() - 1])
+    } else {
+        None
+    }
+}
+
+fn lookup<'a>(raw: &'a Value, path: &[Segment]) -> Option<&'a Value> {
+    let mut value = raw;
+    for segment in path {
+        value = match *segment {
+            Segment::Key(ref key) => value.get(key.as_str())?,
+            Segment::Index(index) => value.get(index)?,
+        }
+    }
+    Some(value)
+}
+
+fn describe(value: &Value) -> String {
+    match *value {
+        Value::Object(_) => "an object".to_owned(),
+        Value::Array(_) => "an array".to_owned(),
+        ref scalar => scalar.to_string(),
+    }
+}
+
+fn render(path: &[Segment]) -> String {
+    let mut rendered = String::new();
+    for segment in path {
+        match *segment {
+            Segment::Key(ref key) => {
+                if !rendered.is_empty() {
+                    rendered.push('.')
+                }
+                rendered.push_str(key)
+            }
+            Segment::Index(index) => rendered.push_str(&format!("[{}]", index)),
+        }
+    }
+    rendered
+}
+
+/// the byte offset of `column` on the 1-based `line` of a text
+pub(crate) fn offset(text: &str, line: usize, column: usize) -> usize {
+    text.split('\n')
+        .take(line.saturating_sub(1))
+        .map(|line| line.len() + 1)
+        .sum::<usize>()
+        + column
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use events::parse;
+
+    const LABEL: &str = include_str!("../data/label.json");
+
+    fn mismatch(event: &str, payload: &str) -> PayloadError {
+        match parse(event, payload) {
+            Err(DeliveryError::Mismatch(e)) => e,
+            other => panic!("expected a mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn locate_mismatched_fields() {
+        let error = mismatch(
+            "label",
+            &LABEL.replace("\"id\": 949738130", "\"id\": \"949738130\""),
+        );
+        assert_eq!("label.id", error.path);
+        assert_eq!("i64", error.expected);
+        assert_eq!("\"949738130\"", error.found);
+        assert_eq!(
+            "label payload does not match at label.id: expected i64, found \"949738130\"",
+            error.to_string()
+        );
+        let error = mismatch(
+            "issues",
+            &include_str!("../data/issues.json").replace("\"name\": \"bug\"", "\"name\": {}"),
+        );
+        assert_eq!("issue.labels[0].name", error.path);
+        assert_eq!("an object", error.found);
+        let error = mismatch("label", &LABEL.replace("\"name\": \":bug: Bugfix\",", ""));
+        assert_eq!("label.name", error.path);
+        assert_eq!("field `name`", error.expected);
+        assert_eq!("nothing", error.found);
+        let error = mismatch(
+            "label",
+            &LABEL
+                .replace(":bug: Bugfix\"", "\\\"}] Bugfix\"")
+                .replace("\"id\": 135493233", "\"id\": null"),
+        );
+        assert_eq!("repository.id", error.path);
+        assert_eq!("null", error.found);
+    }
+
+    #[test]
+    fn reject_invalid_json() {
+        match parse("label", "{") {
+            Err(DeliveryError::InvalidJson { ref event, .. }) => assert_eq!("label", event),
+            other => panic!("expected invalid json, got {:?}", other),
+        }
+    }
+}
@@ -6,12 +6,101 @@ extern crate serde;
 extern crate serde_json;
 
 use case::CaseExt;
+use error::{self, DeliveryError, PayloadError};
+use fields::{Timestamp, UnixTimestamp, Url};
+use ids::{InstallationId, NodeId, RepoId, UserId};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+/// Typed actions of the events which distinguish between activities
+pub mod actions {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::fmt;
+
+    // generated from the documented action fields of events.rs.in
+    include!(concat!(env!("OUT_DIR"), "/actions.rs"));
+}
+
+use self::actions::*;
 
 // generated Event enum goes here
 
 // Enumeration of availble Github events
 include!(concat!(env!("OUT_DIR"), "/events.rs"));
 
+// Events serialize as github sends them, generated from events.rs.in
+include!(concat!(env!("OUT_DIR"), "/serialize_event.rs"));
+
+// The names of modeled events, generated from events.txt
+include!(concat!(env!("OUT_DIR"), "/event_names.rs"));
+
+// `Event::name`, `Event::action` and the accessors of the fields events share,
+// generated from events.txt and events.rs.in
+include!(concat!(env!("OUT_DIR"), "/event_accessors.rs"));
+
+/// parses a payload github sent for an event, falling back to `Event::Unknown`
+/// for events which are not modeled. Payloads which are not json or do not
+/// match the model of their event fail to parse
+pub fn parse(event: &str, payload: &str) -> Result<Event, DeliveryError> {
+    parse_raw(event, payload)?.map_err(|(mismatch, _)| DeliveryError::Mismatch(mismatch))
+}
+
+/// parses a payload like `parse`, handing back the raw json of payloads which
+/// do not match the model of their event along with where they did not
+pub(crate) fn parse_raw(
+    event: &str,
+    payload: &str,
+) -> Result<Result<Event, (PayloadError, serde_json::Value)>, DeliveryError> {
+    // patching raw payload with camelized name field for enum deserialization
+    let patched = patch_payload_json(event, payload);
+    let mismatch = match serde_json::from_str::<Event>(&patched) {
+        Ok(parsed) => return Ok(Ok(parsed)),
+        Err(e) => e,
+    };
+    let raw = serde_json::from_str::<serde_json::Value>(payload).map_err(|e| {
+        DeliveryError::InvalidJson {
+            event: event.to_owned(),
+            message: e.to_string(),
+        }
+    })?;
+    if EVENTS.contains(&event) {
+        // serde read the patched name before the payload
+        let patch = patched.len() - payload.len() - 1;
+        let offset =
+            error::offset(&patched, mismatch.line(), mismatch.column()).saturating_sub(patch);
+        let mismatch = PayloadError::new(event, payload, &raw, offset, &mismatch);
+        Ok(Err((mismatch, raw)))
+    } else {
+        Ok(Ok(Event::Unknown {
+            name: event.to_owned(),
+            raw,
+        }))
+    }
+}
+
+/// reads only the `action` of a payload github sent for an event, if it has one
+pub(crate) fn parse_action(event: &str, payload: &str) -> Result<Option<String>, DeliveryError> {
+    serde_json::from_str::<PayloadAction>(payload)
+        .map(|payload| payload.action)
+        .map_err(|e| DeliveryError::InvalidJson {
+            event: event.to_owned(),
+            message: e.to_string(),
+        })
+}
+
+impl Event {
+    /// the fallback for a payload which does not match the model of its event
+    pub(crate) fn unparsed(error: PayloadError, raw: serde_json::Value) -> Event {
+        Event::Unparsed {
+            name: error.event,
+            raw,
+            path: error.path,
+            expected: error.expected,
+            found: error.found,
+            message: error.message,
+        }
+    }
+}
+
 /// to support enum deserialization, we need to
 /// patch the raw json from github with a field for the enum
 /// name
@@ -32,3 +121,501 @@ impl Default for Value {
         }
     }
 }
+
+/// A repository an event concerns, whichever representation
+/// of it the event carries
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RepositoryRef<'a> {
+    pub id: RepoId,
+    pub node_id: &'a NodeId,
+    pub name: &'a str,
+    /// i.e. `owner/name`
+    pub full_name: &'a str,
+    /// the login of the repository's owner
+    pub owner: &'a str,
+    pub private: bool,
+    pub html_url: &'a str,
+}
+
+/// The account which triggered an event
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AccountRef<'a> {
+    pub id: UserId,
+    pub login: &'a str,
+    /// i.e. `User` or `Bot`
+    pub account_type: &'a str,
+    pub html_url: &'a str,
+}
+
+/// The organization an event concerns
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrganizationRef<'a> {
+    /// organizations share the id space of user accounts
+    pub id: UserId,
+    pub node_id: &'a NodeId,
+    pub login: &'a str,
+}
+
+/// The github app installation an event was sent to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InstallationRef {
+    pub id: InstallationId,
+}
+
+macro_rules! repository_ref {
+    ($($repository:ty),*) => {
+        $(
+            impl<'a> From<&'a $repository> for RepositoryRef<'a> {
+                fn from(repository: &'a $repository) -> RepositoryRef<'a> {
+                    RepositoryRef {
+                        id: repository.id,
+                        node_id: &repository.node_id,
+                        name: &repository.name,
+                        full_name: &repository.full_name,
+                        owner: &repository.owner.login,
+                        private: repository.private,
+                        html_url: repository.html_url.as_str(),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+repository_ref!(Repo, Repository, Repository2);
+
+macro_rules! account_ref {
+    ($($account:ty),*) => {
+        $(
+            impl<'a> From<&'a $account> for AccountRef<'a> {
+                fn from(account: &'a $account) -> AccountRef<'a> {
+                    AccountRef {
+                        id: account.id,
+                        login: &account.login,
+                        account_type: &account._type,
+                        html_url: account.html_url.as_str(),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+account_ref!(User, Sender);
+
+impl<'a> From<&'a Organization> for OrganizationRef<'a> {
+    fn from(organization: &'a Organization) -> OrganizationRef<'a> {
+        OrganizationRef {
+            id: organization.id,
+            node_id: &organization.node_id,
+            login: &organization.login,
+        }
+    }
+}
+
+macro_rules! installation_ref {
+    ($($installation:ty),*) => {
+        $(
+            impl<'a> From<&'a $installation> for InstallationRef {
+                fn from(installation: &'a $installation) -> InstallationRef {
+                    InstallationRef { id: installation.id }
+                }
+            }
+        )*
+    };
+}
+
+installation_ref!(Installation, Installation1, Installation3);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn parse(event: &str, payload: &str) -> Event {
+        serde_json::from_str(&patch_payload_json(event, payload)).unwrap()
+    }
+
+    #[test]
+    fn event_accessors() {
+        let issues = parse("issues", include_str!("../data/issues.json"));
+        assert_eq!("issues", issues.name());
+        assert_eq!(Some("edited"), issues.action());
+        let repository = issues.repository().unwrap();
+        assert_eq!("Codertocat/Hello-World", repository.full_name);
+        assert_eq!("Codertocat", repository.owner);
+        let sender = issues.sender().unwrap();
+        assert_eq!(("Codertocat", "User"), (sender.login, sender.account_type));
+        assert_eq!(None, issues.organization());
+        assert_eq!(None, issues.installation());
+
+        let create = parse("create", include_str!("../data/create.json"));
+        assert_eq!("create", create.name());
+        assert_eq!(None, create.action());
+        assert!(create.repository().is_some());
+    }
+
+    #[test]
+    fn typed_actions() {
+        match parse("issues", include_str!("../data/issues.json")) {
+            Event::Issues { action, .. } => assert_eq!(IssuesAction::Edited, action),
+            _ => panic!("expected an issues event"),
+        }
+        assert_eq!(
+            PullRequestAction::Synchronize,
+            PullRequestAction::from("synchronize")
+        );
+        let action = serde_json::from_str::<PullRequestAction>("\"auto_merge_enabled\"").unwrap();
+        assert_eq!(
+            PullRequestAction::Other("auto_merge_enabled".to_owned()),
+            action
+        );
+        assert_eq!("auto_merge_enabled", action.to_string());
+        assert_eq!(
+            "review_requested",
+            PullRequestAction::ReviewRequested.as_str()
+        );
+    }
+
+    #[cfg(feature = "typed-fields")]
+    #[test]
+    fn typed_fields() {
+        match parse("issues", include_str!("../data/issues.json")) {
+            Event::Issues { issue, .. } => {
+                assert_eq!(Some("api.github.com"), issue.url.host_str());
+                assert_eq!(1527711512, issue.created_at.timestamp());
+                assert_eq!(None, issue.closed_at);
+            }
+            _ => panic!("expected an issues event"),
+        }
+    }
+
+    #[test]
+    fn round_trip_fixtures() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+        for fixture in fs::read_dir(fixtures).unwrap() {
+            let path = fixture.unwrap().path();
+            let name = path.file_stem().unwrap().to_str().unwrap();
+            let payload = fs::read_to_string(&path).unwrap();
+            let event = parse(name, &payload);
+            assert_eq!(name, event.name());
+            assert!(EVENTS.contains(&name), "{} is listed in events.txt", name);
+            let serialized = serde_json::to_string(&event).unwrap();
+            assert_eq!(event, parse(name, &serialized), "{} round trips", name);
+            // typed fields normalize how timestamps and urls are formatted
+            if cfg!(not(feature = "typed-fields")) {
+                assert_eq!(
+                    serde_json::from_str::<serde_json::Value>(&payload).unwrap(),
+                    serde_json::from_str::<serde_json::Value>(&serialized).unwrap(),
+                    "{} serializes as github sent it",
+                    name
+                )
+            }
+        }
+    }
+
+    #[test]
+    fn fall_back_to_unknown_events() {
+        let payload = r#"{"action":"created","sponsorship":{}}"#;
+        let event = super::parse("sponsorship", payload).unwrap();
+        match event {
+            Event::Unknown { ref name, .. } => assert_eq!("sponsorship", name),
+            _ => panic!("expected an unknown event"),
+        }
+        assert_eq!("sponsorship", event.name());
+        assert_eq!(Some("created"), event.action());
+        assert_eq!(payload, serde_json::to_string(&event).unwrap());
+        let payload = r#"{"action": "started"}"#;
+        let error = match super::parse("watch", payload) {
+            Err(DeliveryError::Mismatch(e)) => e,
+            other => panic!("expected a mismatch, got {:?}", other),
+        };
+        assert_eq!("repository", error.path);
+        let (error, raw) = match super::parse_raw("watch", payload) {
+            Ok(Err(mismatch)) => mismatch,
+            other => panic!("expected a mismatch, got {:?}", other),
+        };
+        match Event::unparsed(error, raw) {
+            Event::Unparsed {
+                ref name,
+                ref raw,
+                ref path,
+                ref expected,
+                ref found,
+                ..
+            } => {
+                assert_eq!("watch", name);
+                assert_eq!(Some("started"), raw["action"].as_str());
+                assert_eq!("repository", path);
+                assert_eq!("field `repository`", expected);
+                assert_eq!("nothing", found);
+            }
+            _ => panic!("expected an unparsed event"),
+        }
+        assert!(super::parse("watch", "{").is_err());
+    }
+
+    #[test]
+    fn workflow_events() {
+        let run = parse("workflow_run", include_str!("../data/workflow_run.json"));
+        assert_eq!(Some("completed"), run.action());
+        assert_eq!(Some("github"), run.organization().map(|o| o.login));
+        assert_eq!(Some(InstallationId(1)), run.installation().map(|i| i.id));
+        match run {
+            Event::WorkflowRun {
+                action,
+                workflow_run,
+                ..
+            } => {
+                assert_eq!(WorkflowRunAction::Completed, action);
+                assert_eq!(Some("success"), workflow_run.conclusion.as_deref());
+                assert_eq!(1, workflow_run.run_attempt);
+                assert_eq!("Update the README", workflow_run.head_commit.message);
+                assert_eq!("changes", workflow_run.pull_requests[0].head._ref);
+            }
+            _ => panic!("expected a workflow_run event"),
+        }
+        match parse("workflow_job", include_str!("../data/workflow_job.json")) {
+            Event::WorkflowJob { workflow_job, .. } => {
+                assert_eq!(Some("failure"), workflow_job.conclusion.as_deref());
+                assert_eq!(
+                    vec!["Set up job", "Run cargo test"],
+                    workflow_job
+                        .steps
+                        .iter()
+                        .map(|step| step.name.as_str())
+                        .collect::<Vec<_>>()
+                );
+            }
+            _ => panic!("expected a workflow_job event"),
+        }
+        let dispatch = parse(
+            "workflow_dispatch",
+            include_str!("../data/workflow_dispatch.json"),
+        );
+        assert_eq!(None, dispatch.action());
+        assert_eq!(None, dispatch.installation());
+        assert_eq!(
+            Some("github/hello-world"),
+            dispatch.repository().map(|r| r.full_name)
+        );
+    }
+
+    #[test]
+    fn security_alert_events() {
+        match parse(
+            "code_scanning_alert",
+            include_str!("../data/code_scanning_alert.json"),
+        ) {
+            Event::CodeScanningAlert { action, alert, .. } => {
+                assert_eq!(CodeScanningAlertAction::Created, action);
+                assert_eq!("error", alert.rule.severity);
+                assert_eq!(Some("high"), alert.rule.security_severity_level.as_deref());
+                let location = alert.most_recent_instance.location;
+                assert_eq!(
+                    ("src/unzip.js", 12),
+                    (location.path.as_str(), location.start_line)
+                );
+            }
+            _ => panic!("expected a code_scanning_alert event"),
+        }
+        match parse(
+            "dependabot_alert",
+            include_str!("../data/dependabot_alert.json"),
+        ) {
+            Event::DependabotAlert { alert, .. } => {
+                assert_eq!("high", alert.security_advisory.severity);
+                assert_eq!("lodash", alert.dependency.package.name);
+            }
+            _ => panic!("expected a dependabot_alert event"),
+        }
+        let secret = parse(
+            "secret_scanning_alert",
+            include_str!("../data/secret_scanning_alert.json"),
+        );
+        assert_eq!(Some("resolved"), secret.action());
+        assert_eq!(Some("github"), secret.organization().map(|o| o.login));
+        let analysis = parse(
+            "security_and_analysis",
+            include_str!("../data/security_and_analysis.json"),
+        );
+        assert_eq!(None, analysis.action());
+        match analysis {
+            Event::SecurityAndAnalysis { changes, .. } => assert_eq!(
+                Some("disabled"),
+                changes
+                    .from
+                    .security_and_analysis
+                    .secret_scanning
+                    .map(|feature| feature.status)
+                    .as_deref()
+            ),
+            _ => panic!("expected a security_and_analysis event"),
+        }
+    }
+
+    #[test]
+    fn discussion_events() {
+        let discussion = parse("discussion", include_str!("../data/discussion.json"));
+        assert_eq!(Some("answered"), discussion.action());
+        match discussion {
+            Event::Discussion {
+                action,
+                discussion,
+                answer,
+                ..
+            } => {
+                assert_eq!(DiscussionAction::Answered, action);
+                assert_eq!("Q&A", discussion.category.name);
+                assert!(discussion.category.is_answerable);
+                assert_eq!(
+                    Some(discussion.answer_html_url.unwrap()),
+                    answer.map(|answer| answer.html_url)
+                );
+            }
+            _ => panic!("expected a discussion event"),
+        }
+        assert_eq!(
+            DiscussionAction::CategoryChanged,
+            DiscussionAction::from("category_changed")
+        );
+        assert_eq!("pinned", DiscussionAction::Pinned.as_str());
+        let comment = parse(
+            "discussion_comment",
+            include_str!("../data/discussion_comment.json"),
+        );
+        assert_eq!(Some("github"), comment.organization().map(|o| o.login));
+        match comment {
+            Event::DiscussionComment {
+                comment,
+                discussion,
+                ..
+            } => assert_eq!(discussion.id, comment.discussion_id),
+            _ => panic!("expected a discussion_comment event"),
+        }
+    }
+
+    #[test]
+    fn projects_and_custom_property_events() {
+        let item = parse(
+            "projects_v2_item",
+            include_str!("../data/projects_v2_item.json"),
+        );
+        assert_eq!("projects_v2_item", item.name());
+        assert_eq!(Some("github"), item.organization().map(|o| o.login));
+        assert_eq!(None, item.repository());
+        match item {
+            Event::ProjectsV2Item {
+                action, changes, ..
+            } => {
+                assert_eq!(ProjectsV2ItemAction::Edited, action);
+                let field_value = changes.unwrap().field_value.unwrap();
+                assert_eq!("Status", field_value.field_name);
+                assert_eq!(
+                    Some("In Progress"),
+                    field_value
+                        .to
+                        .as_ref()
+                        .and_then(|to| to.get("name"))
+                        .and_then(|name| name.as_str())
+                );
+            }
+            _ => panic!("expected a projects_v2_item event"),
+        }
+        match parse("projects_v2", include_str!("../data/projects_v2.json")) {
+            Event::ProjectsV2 { projects_v2, .. } => {
+                assert_eq!(
+                    ("Roadmap", 4),
+                    (projects_v2.title.as_str(), projects_v2.number)
+                )
+            }
+            _ => panic!("expected a projects_v2 event"),
+        }
+        let values = parse(
+            "custom_property_values",
+            include_str!("../data/custom_property_values.json"),
+        );
+        assert!(values.repository().is_some());
+        match values {
+            Event::CustomPropertyValues {
+                new_property_values,
+                old_property_values,
+                ..
+            } => {
+                assert_eq!("teams", new_property_values[1].property_name);
+                assert_eq!(None, old_property_values[1].value);
+            }
+            _ => panic!("expected a custom_property_values event"),
+        }
+        // deletions only carry the name of the deleted property
+        let mut deleted =
+            serde_json::from_str::<serde_json::Value>(include_str!("../data/custom_property.json"))
+                .unwrap();
+        deleted["action"] = "deleted".into();
+        deleted["definition"] =
+            serde_json::from_str(r#"{"property_name": "environment"}"#).unwrap();
+        match parse("custom_property", &serde_json::to_string(&deleted).unwrap()) {
+            Event::CustomProperty {
+                action, definition, ..
+            } => {
+                assert_eq!(CustomPropertyAction::Deleted, action);
+                assert_eq!(None, definition.value_type);
+            }
+            _ => panic!("expected a custom_property event"),
+        }
+    }
+
+    #[test]
+    fn merge_queue_and_review_thread_events() {
+        let merge_group = parse("merge_group", include_str!("../data/merge_group.json"));
+        assert_eq!(Some("checks_requested"), merge_group.action());
+        assert_eq!(None, merge_group.organization());
+        match merge_group {
+            Event::MergeGroup {
+                action,
+                reason,
+                merge_group,
+                ..
+            } => {
+                assert_eq!(MergeGroupAction::ChecksRequested, action);
+                assert_eq!(None, reason);
+                assert_eq!("refs/heads/master", merge_group.base_ref);
+                assert_eq!(merge_group.head_sha, merge_group.head_commit.id);
+            }
+            _ => panic!("expected a merge_group event"),
+        }
+        let thread = parse(
+            "pull_request_review_thread",
+            include_str!("../data/pull_request_review_thread.json"),
+        );
+        assert_eq!("pull_request_review_thread", thread.name());
+        assert_eq!(Some("Codertocat"), thread.sender().map(|s| s.login));
+        match thread {
+            Event::PullRequestReviewThread {
+                action,
+                thread,
+                pull_request,
+                ..
+            } => {
+                assert_eq!(PullRequestReviewThreadAction::Resolved, action);
+                assert_eq!(pull_request.url, thread.comments[0].pull_request_url);
+            }
+            _ => panic!("expected a pull_request_review_thread event"),
+        }
+    }
+
+    #[test]
+    fn names_are_event_headers() {
+        for (event, payload) in &[
+            ("watch", include_str!("../data/watch.json")),
+            ("label", include_str!("../data/label.json")),
+            (
+                "pull_request_review",
+                include_str!("../data/pull_request_review.json"),
+            ),
+        ] {
+            assert_eq!(*event, parse(event, payload).name())
+        }
+    }
+}
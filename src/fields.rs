@@ -0,0 +1,221 @@
+//! Types of the timestamp and url fields of events
+//!
+//! By default these are kept as github sends them. With the `typed-fields`
+//! feature timestamps deserialize as `chrono` datetimes, whether github sent
+//! them as ISO-8601 strings or as seconds since the unix epoch, and urls
+//! deserialize as parsed `url` urls. Url templates, i.e.
+//! `https://api.github.com/repos/o/r/issues{/number}`, are always strings
+
+#[cfg(not(feature = "typed-fields"))]
+mod plain {
+    /// An ISO-8601 timestamp, i.e. `2018-05-30T20:18:50Z`
+    pub type Timestamp = String;
+
+    /// A timestamp in seconds since the unix epoch
+    pub type UnixTimestamp = i64;
+
+    /// An absolute url
+    pub type Url = String;
+}
+
+#[cfg(not(feature = "typed-fields"))]
+pub use self::plain::{Timestamp, UnixTimestamp, Url};
+
+#[cfg(feature = "typed-fields")]
+pub use self::typed::{Timestamp, UnixTimestamp, Url};
+
+#[cfg(feature = "typed-fields")]
+mod typed {
+    use chrono::{DateTime, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_json::Value;
+    use std::fmt;
+    use std::ops::Deref;
+    use url;
+
+    /// A point in time, deserialized from either an ISO-8601 string
+    /// or seconds since the unix epoch
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct Timestamp(DateTime<Utc>);
+
+    /// Github sends some timestamps as seconds since the unix epoch,
+    /// these are typed the same as any other timestamp
+    pub type UnixTimestamp = Timestamp;
+
+    impl Timestamp {
+        /// parses ISO-8601 timestamps with an offset, and those without
+        /// one which github sends in utc, i.e. `2018-04-25 20:42:10`
+        pub fn parse(timestamp: &str) -> Option<Timestamp> {
+            DateTime::parse_from_rfc3339(timestamp)
+                .map(|datetime| datetime.with_timezone(&Utc))
+                .or_else(|_| {
+                    NaiveDateTime::parse_from_str(
+                        timestamp.trim_end_matches(" UTC"),
+                        "%Y-%m-%d %H:%M:%S",
+                    )
+                    .map(|datetime| Utc.from_utc_datetime(&datetime))
+                })
+                .ok()
+                .map(Timestamp)
+        }
+
+        /// the timestamp `seconds` after the unix epoch
+        pub fn from_unix(seconds: i64) -> Option<Timestamp> {
+            Utc.timestamp_opt(seconds, 0).single().map(Timestamp)
+        }
+
+        pub fn into_inner(self) -> DateTime<Utc> {
+            self.0
+        }
+    }
+
+    impl Deref for Timestamp {
+        type Target = DateTime<Utc>;
+
+        fn deref(&self) -> &DateTime<Utc> {
+            &self.0
+        }
+    }
+
+    impl From<DateTime<Utc>> for Timestamp {
+        fn from(datetime: DateTime<Utc>) -> Timestamp {
+            Timestamp(datetime)
+        }
+    }
+
+    impl From<Timestamp> for DateTime<Utc> {
+        fn from(timestamp: Timestamp) -> DateTime<Utc> {
+            timestamp.0
+        }
+    }
+
+    impl fmt::Display for Timestamp {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(&self.0.to_rfc3339())
+        }
+    }
+
+    /// Timestamps serialize as ISO-8601 strings in utc, i.e. `2018-05-30T20:18:50Z`,
+    /// regardless of how github sent them
+    impl Serialize for Timestamp {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serializer.serialize_str(&self.0.to_rfc3339_opts(SecondsFormat::Secs, true))
+        }
+    }
+
+    impl Deserialize for Timestamp {
+        fn deserialize<D>(deserializer: D) -> Result<Timestamp, D::Error>
+        where
+            D: Deserializer,
+        {
+            match Value::deserialize(deserializer)? {
+                Value::String(timestamp) => Timestamp::parse(&timestamp)
+                    .ok_or_else(|| D::Error::custom(format!("invalid timestamp {}", timestamp))),
+                Value::Number(seconds) => seconds
+                    .as_i64()
+                    .and_then(Timestamp::from_unix)
+                    .ok_or_else(|| D::Error::custom(format!("invalid timestamp {}", seconds))),
+                other => Err(D::Error::custom(format!(
+                    "expected a timestamp, found {}",
+                    other
+                ))),
+            }
+        }
+    }
+
+    /// An absolute url
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct Url(url::Url);
+
+    impl Url {
+        pub fn as_str(&self) -> &str {
+            self.0.as_str()
+        }
+
+        pub fn into_inner(self) -> url::Url {
+            self.0
+        }
+    }
+
+    impl Deref for Url {
+        type Target = url::Url;
+
+        fn deref(&self) -> &url::Url {
+            &self.0
+        }
+    }
+
+    impl From<url::Url> for Url {
+        fn from(url: url::Url) -> Url {
+            Url(url)
+        }
+    }
+
+    impl From<Url> for url::Url {
+        fn from(url: Url) -> url::Url {
+            url.0
+        }
+    }
+
+    impl fmt::Display for Url {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.as_str())
+        }
+    }
+
+    impl Serialize for Url {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serializer.serialize_str(self.as_str())
+        }
+    }
+
+    impl Deserialize for Url {
+        fn deserialize<D>(deserializer: D) -> Result<Url, D::Error>
+        where
+            D: Deserializer,
+        {
+            let url = String::deserialize(deserializer)?;
+            url::Url::parse(&url)
+                .map(Url)
+                .map_err(|e| D::Error::custom(format!("invalid url {}: {}", url, e)))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "typed-fields"))]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn deserialize_timestamps() {
+        let iso = serde_json::from_str::<Timestamp>("\"2018-05-30T20:18:50Z\"").unwrap();
+        let offset = serde_json::from_str::<Timestamp>("\"2018-05-30T22:18:50+02:00\"").unwrap();
+        let unix = serde_json::from_str::<UnixTimestamp>("1527711530").unwrap();
+        let naive = serde_json::from_str::<Timestamp>("\"2018-05-30 20:18:50\"").unwrap();
+        assert_eq!(iso, offset);
+        assert_eq!(iso, unix);
+        assert_eq!(iso, naive);
+        assert_eq!("2018-05-30T20:18:50+00:00", iso.to_string());
+        assert_eq!(
+            "\"2018-05-30T20:18:50Z\"",
+            serde_json::to_string(&unix).unwrap()
+        );
+        assert!(serde_json::from_str::<Timestamp>("\"yesterday\"").is_err());
+        assert!(serde_json::from_str::<Timestamp>("true").is_err());
+    }
+
+    #[test]
+    fn deserialize_urls() {
+        let url = serde_json::from_str::<Url>("\"https://github.com/Codertocat\"").unwrap();
+        assert_eq!(Some("github.com"), url.host_str());
+        assert!(serde_json::from_str::<Url>("\"not a url\"").is_err());
+    }
+}
@@ -0,0 +1,309 @@
+//! Composable filters which only pass deliveries of interest on to hooks
+//!
+//! ```ignore
+//! hub.handle("push", only_repo("org/name").and(only_branch("main")).hook(deploy));
+//! ```
+
+use super::Delivery;
+use events::Event;
+use hook::{FallibleHook, Hook, HookError, HookOutcome};
+
+/// A predicate on deliveries
+pub trait Filter: Send + Sync {
+    /// whether the delivery should be passed on
+    fn accepts(&self, delivery: &Delivery) -> bool;
+
+    /// accepts deliveries which both this and another filter accept
+    fn and<F>(self, other: F) -> And<Self, F>
+    where
+        Self: Sized,
+        F: Filter,
+    {
+        And(self, other)
+    }
+
+    /// accepts deliveries which either this or another filter accepts
+    fn or<F>(self, other: F) -> Or<Self, F>
+    where
+        Self: Sized,
+        F: Filter,
+    {
+        Or(self, other)
+    }
+
+    /// wraps a hook so that it only handles deliveries this filter accepts.
+    /// Fallible hooks report the deliveries they did not handle as skipped
+    fn hook<H>(self, hook: H) -> Filtered<Self, H>
+    where
+        Self: Sized,
+    {
+        Filtered { filter: self, hook }
+    }
+}
+
+impl<F> Filter for F
+where
+    F: Fn(&Delivery) -> bool,
+    F: Sync + Send,
+{
+    fn accepts(&self, delivery: &Delivery) -> bool {
+        self(delivery)
+    }
+}
+
+/// Accepts deliveries both filters accept
+pub struct And<A, B>(A, B);
+
+impl<A: Filter, B: Filter> Filter for And<A, B> {
+    fn accepts(&self, delivery: &Delivery) -> bool {
+        self.0.accepts(delivery) && self.1.accepts(delivery)
+    }
+}
+
+/// Accepts deliveries either filter accepts
+pub struct Or<A, B>(A, B);
+
+impl<A: Filter, B: Filter> Filter for Or<A, B> {
+    fn accepts(&self, delivery: &Delivery) -> bool {
+        self.0.accepts(delivery) || self.1.accepts(delivery)
+    }
+}
+
+/// A hook which only handles deliveries its filter accepts
+pub struct Filtered<F, H> {
+    filter: F,
+    hook: H,
+}
+
+impl<F: Filter, H: Hook> Hook for Filtered<F, H> {
+    fn handle(&self, delivery: &Delivery) {
+        if self.filter.accepts(delivery) {
+            self.hook.handle(delivery)
+        }
+    }
+}
+
+impl<F: Filter, H: FallibleHook> FallibleHook for Filtered<F, H> {
+    fn handle(&self, delivery: &Delivery) -> Result<HookOutcome, HookError> {
+        if self.filter.accepts(delivery) {
+            self.hook.handle(delivery)
+        } else {
+            Ok(HookOutcome::Skipped)
+        }
+    }
+}
+
+/// Accepts deliveries concerning a repository, by its `owner/name` full name
+pub struct OnlyRepo(String);
+
+/// accepts deliveries concerning the repository with the given `owner/name`
+pub fn only_repo<R: Into<String>>(full_name: R) -> OnlyRepo {
+    OnlyRepo(full_name.into())
+}
+
+impl Filter for OnlyRepo {
+    fn accepts(&self, delivery: &Delivery) -> bool {
+        delivery
+            .payload
+            .repository()
+            .map(|repository| repository.full_name.eq_ignore_ascii_case(&self.0))
+            .unwrap_or(false)
+    }
+}
+
+/// Accepts deliveries concerning a branch
+pub struct OnlyBranch(String);
+
+/// accepts pushes to, branch creations and deletions of, pull requests
+/// and merge groups against, statuses of commits on and check suites,
+/// workflow runs and workflow jobs for the head of the given branch
+pub fn only_branch<B: Into<String>>(branch: B) -> OnlyBranch {
+    OnlyBranch(branch.into())
+}
+
+impl Filter for OnlyBranch {
+    fn accepts(&self, delivery: &Delivery) -> bool {
+        branches(&delivery.payload).contains(&self.0.as_str())
+    }
+}
+
+/// Rejects deliveries sent by a type of account
+pub struct NotSenderType(String);
+
+/// rejects deliveries whose sender is of the given type, i.e. `Bot`
+pub fn not_sender_type<T: Into<String>>(sender_type: T) -> NotSenderType {
+    NotSenderType(sender_type.into())
+}
+
+impl Filter for NotSenderType {
+    fn accepts(&self, delivery: &Delivery) -> bool {
+        delivery.payload.sender().map(|sender| sender.account_type) != Some(self.0.as_str())
+    }
+}
+
+/// Accepts deliveries concerning a label
+pub struct HasLabel(String);
+
+/// accepts deliveries concerning labels, issues or pull requests
+/// with the given label
+pub fn has_label<L: Into<String>>(label: L) -> HasLabel {
+    HasLabel(label.into())
+}
+
+impl Filter for HasLabel {
+    fn accepts(&self, delivery: &Delivery) -> bool {
+        labels(&delivery.payload).contains(&self.0.as_str())
+    }
+}
+
+fn branches(event: &Event) -> Vec<&str> {
+    match *event {
+        Event::Push { ref _ref, .. } => _ref.strip_prefix("refs/heads/").into_iter().collect(),
+        Event::MergeGroup {
+            ref merge_group, ..
+        } => merge_group
+            .base_ref
+            .strip_prefix("refs/heads/")
+            .into_iter()
+            .collect(),
+        Event::Create {
+            ref _ref,
+            ref ref_type,
+            ..
+        }
+        | Event::Delete {
+            ref _ref,
+            ref ref_type,
+            ..
+        } if ref_type == "branch" => vec![_ref],
+        Event::PullRequest {
+            ref pull_request, ..
+        } => vec![&pull_request.base._ref],
+        Event::PullRequestReview {
+            ref pull_request, ..
+        }
+        | Event::PullRequestReviewComment {
+            ref pull_request, ..
+        }
+        | Event::PullRequestReviewThread {
+            ref pull_request, ..
+        } => vec![&pull_request.base._ref],
+        Event::Status { ref branches, .. } => branches.iter().map(|b| b.name.as_str()).collect(),
+        Event::CheckSuite {
+            ref check_suite, ..
+        } => check_suite.head_branch.as_deref().into_iter().collect(),
+        Event::WorkflowRun {
+            ref workflow_run, ..
+        } => vec![&workflow_run.head_branch],
+        Event::WorkflowJob {
+            ref workflow_job, ..
+        } => workflow_job.head_branch.as_deref().into_iter().collect(),
+        _ => vec![],
+    }
+}
+
+fn labels(event: &Event) -> Vec<&str> {
+    match *event {
+        Event::Label { ref label, .. } => vec![&label.name],
+        Event::Issues { ref issue, .. } | Event::IssueComment { ref issue, .. } => {
+            issue.labels.iter().map(|l| l.name.as_str()).collect()
+        }
+        Event::PullRequest {
+            ref pull_request,
+            ref label,
+            ..
+        } => pull_request
+            .labels
+            .iter()
+            .chain(label)
+            .map(|l| l.name.as_str())
+            .collect(),
+        Event::PullRequestReview {
+            ref pull_request, ..
+        }
+        | Event::PullRequestReviewComment {
+            ref pull_request, ..
+        }
+        | Event::PullRequestReviewThread {
+            ref pull_request, ..
+        } => pull_request
+            .labels
+            .iter()
+            .map(|l| l.name.as_str())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn delivery(event: &str, payload: &str) -> Delivery {
+        Delivery::new(
+            "72d3162e-cc78-11e3-81ab-4c9367dc0958",
+            event,
+            payload,
+            None,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn filter_deliveries() {
+        let issues = delivery("issues", include_str!("../data/issues.json"));
+        let review = delivery(
+            "pull_request_review",
+            include_str!("../data/pull_request_review.json"),
+        );
+        assert!(only_repo("codertocat/hello-world").accepts(&issues));
+        assert!(!only_repo("softprops/afterparty").accepts(&issues));
+        assert!(only_branch("master").accepts(&review));
+        assert!(!only_branch("master").accepts(&issues));
+        assert!(not_sender_type("Bot").accepts(&issues));
+        assert!(!not_sender_type("User").accepts(&issues));
+        assert!(has_label("bug").accepts(&issues));
+        assert!(!has_label("bug").accepts(&review));
+        assert!(has_label("bug").or(only_branch("master")).accepts(&review));
+        assert!(!has_label("bug").and(only_branch("master")).accepts(&review));
+        assert!((|d: &Delivery| d.event == "issues").accepts(&issues));
+        let merge_group = delivery("merge_group", include_str!("../data/merge_group.json"));
+        assert!(only_branch("master").accepts(&merge_group));
+        assert!(!only_branch("gh-readonly-queue").accepts(&merge_group));
+        let check_suite = delivery("check_suite", include_str!("../data/check_suite.json"));
+        assert!(only_branch("master").accepts(&check_suite));
+        assert!(!only_branch("changes").accepts(&check_suite));
+        let detached = include_str!("../data/check_suite.json")
+            .replace("\"head_branch\": \"master\"", "\"head_branch\": null");
+        let detached = delivery("check_suite", &detached);
+        assert!(matches!(detached.payload, Event::CheckSuite { .. }));
+        assert!(!only_branch("master").accepts(&detached));
+        let workflow_run = delivery("workflow_run", include_str!("../data/workflow_run.json"));
+        assert!(only_branch("changes").accepts(&workflow_run));
+        assert!(!only_branch("master").accepts(&workflow_run));
+        let workflow_job = delivery("workflow_job", include_str!("../data/workflow_job.json"));
+        assert!(only_branch("changes").accepts(&workflow_job));
+        assert!(!only_branch("master").accepts(&workflow_job));
+    }
+
+    #[test]
+    fn filter_hooks() {
+        let issues = delivery("issues", include_str!("../data/issues.json"));
+        let handled = Arc::new(AtomicUsize::new(0));
+        let counter = handled.clone();
+        let hook = has_label("bug").hook(move |_: &Delivery| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        Hook::handle(&hook, &issues);
+        let skipped = has_label("wontfix")
+            .hook(|_: &Delivery| -> Result<_, HookError> { Ok(HookOutcome::Handled) });
+        assert_eq!(
+            Ok(HookOutcome::Skipped),
+            FallibleHook::handle(&skipped, &issues)
+        );
+        assert_eq!(1, handled.load(Ordering::SeqCst));
+    }
+}
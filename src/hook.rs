@@ -1,9 +1,9 @@
 use super::Delivery;
-use crypto::hmac::Hmac;
-use crypto::mac::Mac;
-use crypto::mac::MacResult;
-use crypto::sha1::Sha1;
-use hex::FromHex;
+use secrets::{SecretProvider, Secrets};
+use std::error::Error;
+use std::fmt;
+use subscription::Subscription;
+use verify::Verifier;
 
 /// Handles webhook deliveries
 pub trait Hook: Send + Sync {
@@ -11,52 +11,216 @@ pub trait Hook: Send + Sync {
     fn handle(&self, delivery: &Delivery);
 }
 
+/// What a fallible hook did with a delivery
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HookOutcome {
+    /// the hook acted on the delivery
+    Handled,
+    /// the hook decided the delivery was not of interest
+    Skipped,
+    /// the hook refused the delivery, i.e. because it failed to authenticate
+    Rejected,
+}
+
+/// Why a fallible hook failed to handle a delivery
+#[derive(Clone, Debug, PartialEq)]
+pub struct HookError {
+    message: String,
+}
+
+impl HookError {
+    pub fn new<M>(message: M) -> HookError
+    where
+        M: Into<String>,
+    {
+        HookError {
+            message: message.into(),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for HookError {}
+
+impl From<String> for HookError {
+    fn from(message: String) -> HookError {
+        HookError::new(message)
+    }
+}
+
+impl<'a> From<&'a str> for HookError {
+    fn from(message: &'a str) -> HookError {
+        HookError::new(message)
+    }
+}
+
+/// Handles webhook deliveries, reporting whether they were handled
+///
+/// Failures of required fallible hooks are reflected in the hub's
+/// response to github, so they show up in github's recent deliveries
+pub trait FallibleHook: Send + Sync {
+    fn handle(&self, delivery: &Delivery) -> Result<HookOutcome, HookError>;
+}
+
+impl<F> FallibleHook for F
+where
+    F: Fn(&Delivery) -> Result<HookOutcome, HookError>,
+    F: Sync + Send,
+{
+    fn handle(&self, delivery: &Delivery) -> Result<HookOutcome, HookError> {
+        self(delivery)
+    }
+}
+
+/// Adapts a hook which can not fail to a fallible one
+pub(crate) struct Infallible<H: Hook>(pub(crate) H);
+
+impl<H: Hook> FallibleHook for Infallible<H> {
+    fn handle(&self, delivery: &Delivery) -> Result<HookOutcome, HookError> {
+        self.0.handle(delivery);
+        Ok(HookOutcome::Handled)
+    }
+}
+
+/// A hook registered with a hub under a name
+pub struct Registered<H: ?Sized> {
+    name: String,
+    required: bool,
+    subscription: Subscription,
+    hook: Box<H>,
+}
+
+impl<H: ?Sized> Registered<H> {
+    pub(crate) fn new(
+        name: String,
+        required: bool,
+        subscription: Subscription,
+        hook: Box<H>,
+    ) -> Registered<H> {
+        Registered {
+            name,
+            required,
+            subscription,
+            hook,
+        }
+    }
+
+    /// the name the hook's results are logged under
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// whether the hook failing fails the delivery
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    /// the events and actions the hook subscribed to
+    pub fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
+
+    pub fn hook(&self) -> &H {
+        &self.hook
+    }
+}
+
 /// A delivery authenticator for hooks
+///
+/// Deliveries are accepted when they are signed with any of the
+/// secrets provided for them which have not yet expired. Registered
+/// with a hub, deliveries which are not are rejected, and answered with
+/// a `401 Unauthorized` when no other hook handled them
 pub struct AuthenticateHook<H: Hook + 'static> {
-    secret: String,
+    verifier: Verifier,
     hook: H,
 }
 
 impl<H: Hook + 'static> AuthenticateHook<H> {
-    pub fn new<S>(secret: S, hook: H) -> AuthenticateHook<H>
+    pub fn new<S>(secrets: S, hook: H) -> AuthenticateHook<H>
     where
-        S: Into<String>,
+        S: Into<Secrets>,
     {
+        AuthenticateHook::with_verifier(Verifier::new(secrets), hook)
+    }
+
+    /// authenticates deliveries with secrets looked up by a provider,
+    /// i.e. per repository, organization or installation
+    pub fn with_provider<P>(provider: P, hook: H) -> AuthenticateHook<H>
+    where
+        P: SecretProvider + 'static,
+    {
+        AuthenticateHook::with_verifier(Verifier::with_provider(provider), hook)
+    }
+
+    /// authenticates deliveries with a preconfigured verifier
+    pub fn with_verifier(verifier: Verifier, hook: H) -> AuthenticateHook<H> {
+        AuthenticateHook { verifier, hook }
+    }
+
+    /// when enabled, deliveries which only carry the legacy sha1
+    /// `X-Hub-Signature` header will be rejected
+    pub fn require_sha256(self, required: bool) -> AuthenticateHook<H> {
         AuthenticateHook {
-            secret: secret.into(),
-            hook: hook,
+            verifier: self.verifier.require_sha256(required),
+            ..self
         }
     }
 
-    fn authenticate(&self, payload: &str, signature: &str) -> bool {
-        // https://developer.github.com/webhooks/securing/#validating-payloads-from-github
-        let sans_prefix = signature[5..signature.len()].as_bytes();
-        match Vec::from_hex(sans_prefix) {
-            Ok(sigbytes) => {
-                let sbytes = self.secret.as_bytes();
-                let mut mac = Hmac::new(Sha1::new(), &sbytes);
-                let pbytes = payload.as_bytes();
-                mac.input(&pbytes);
-                // constant time comparison
-                mac.result() == MacResult::new(&sigbytes)
+    /// returns the name of the secret which authenticated the payload, if any
+    fn authenticate(
+        &self,
+        payload: &str,
+        signature: Option<&str>,
+        signature_256: Option<&str>,
+    ) -> Option<String> {
+        match self.verifier.verify(payload, signature, signature_256) {
+            Ok(name) => Some(name),
+            Err(e) => {
+                error!("rejecting request: {}", e);
+                None
             }
-            Err(_) => false,
         }
     }
 }
 
-impl<H: Hook + 'static> Hook for AuthenticateHook<H> {
-    fn handle(&self, delivery: &Delivery) {
-        if let Some(sig) = delivery.signature {
-            if self.authenticate(delivery.unparsed_payload, sig) {
-                self.hook.handle(delivery)
-            } else {
-                error!("failed to authenticate request");
+impl<H: Hook + 'static> FallibleHook for AuthenticateHook<H> {
+    fn handle(&self, delivery: &Delivery) -> Result<HookOutcome, HookError> {
+        match self.authenticate(
+            &delivery.unparsed_payload,
+            delivery.signature.as_deref(),
+            delivery.signature_256.as_deref(),
+        ) {
+            Some(name) => {
+                info!(
+                    "authenticated delivery {} with secret '{}'",
+                    delivery.id, name
+                );
+                self.hook.handle(delivery);
+                Ok(HookOutcome::Handled)
             }
+            None => Ok(HookOutcome::Rejected),
         }
     }
 }
 
+/// Hooks which can not fail drop deliveries which are not authentic
+impl<H: Hook + 'static> Hook for AuthenticateHook<H> {
+    fn handle(&self, delivery: &Delivery) {
+        // rejections are logged while authenticating
+        let _ = FallibleHook::handle(self, delivery);
+    }
+}
+
 impl<F> Hook for F
 where
     F: Fn(&Delivery),
@@ -71,22 +235,140 @@ where
 mod tests {
     use super::super::Delivery;
     use super::*;
+    use crypto::digest::Digest;
     use crypto::hmac::Hmac;
     use crypto::mac::Mac;
     use crypto::sha1::Sha1;
+    use crypto::sha2::Sha256;
     use hex::ToHex;
+    use secrets::{MemorySecrets, Secret, SecretScope, Secrets};
+    use std::time::{Duration, SystemTime};
 
-    #[test]
-    fn authenticate_signatures() {
-        let authenticated = AuthenticateHook::new("secret", |_: &Delivery| {});
-        let payload = r#"{"zen": "Approachable is better than simple."}"#;
-        let secret = "secret";
+    fn sign<D: Digest>(digest: D, secret: &str, payload: &str) -> String {
         let sbytes = secret.as_bytes();
         let pbytes = payload.as_bytes();
-        let mut mac = Hmac::new(Sha1::new(), &sbytes);
+        let mut mac = Hmac::new(digest, &sbytes);
         mac.input(&pbytes);
         let mut signature = String::new();
         mac.result().code().write_hex(&mut signature).unwrap();
-        assert!(authenticated.authenticate(payload, format!("sha1={}", signature).as_ref()))
+        signature
+    }
+
+    #[test]
+    fn reject_unauthenticated_deliveries() {
+        let authenticated =
+            AuthenticateHook::new("secret", |_: &Delivery| panic!("unauthenticated"));
+        let delivery = Delivery::new(
+            "72d3162e-cc78-11e3-81ab-4c9367dc0958",
+            "watch",
+            include_str!("../data/watch.json"),
+            None,
+            Some("sha256=00"),
+        )
+        .unwrap();
+        assert_eq!(
+            Ok(HookOutcome::Rejected),
+            FallibleHook::handle(&authenticated, &delivery)
+        );
+        Hook::handle(&authenticated, &delivery)
+    }
+
+    #[test]
+    fn authenticate_signatures() {
+        let authenticated = AuthenticateHook::new("secret", |_: &Delivery| {});
+        let payload = r#"{"zen": "Approachable is better than simple."}"#;
+        let signature = sign(Sha1::new(), "secret", payload);
+        assert_eq!(
+            Some("default".to_owned()),
+            authenticated.authenticate(payload, Some(format!("sha1={}", signature).as_ref()), None)
+        )
+    }
+
+    #[test]
+    fn authenticate_sha256_signatures() {
+        let authenticated = AuthenticateHook::new("secret", |_: &Delivery| {});
+        let payload = r#"{"zen": "Approachable is better than simple."}"#;
+        let sha1 = format!("sha1={}", sign(Sha1::new(), "secret", payload));
+        let sha256 = format!("sha256={}", sign(Sha256::new(), "secret", payload));
+        assert!(authenticated
+            .authenticate(payload, Some(sha1.as_ref()), Some(sha256.as_ref()))
+            .is_some());
+        assert!(authenticated
+            .authenticate(payload, Some(sha1.as_ref()), Some("sha256=00"))
+            .is_none());
+        assert!(authenticated
+            .authenticate(payload, None, Some(sha1.as_ref()))
+            .is_none())
+    }
+
+    #[test]
+    fn require_sha256_signatures() {
+        let authenticated = AuthenticateHook::new("secret", |_: &Delivery| {}).require_sha256(true);
+        let payload = r#"{"zen": "Approachable is better than simple."}"#;
+        let sha1 = format!("sha1={}", sign(Sha1::new(), "secret", payload));
+        let sha256 = format!("sha256={}", sign(Sha256::new(), "secret", payload));
+        assert!(authenticated
+            .authenticate(payload, Some(sha1.as_ref()), None)
+            .is_none());
+        assert!(authenticated
+            .authenticate(payload, Some(sha1.as_ref()), Some(sha256.as_ref()))
+            .is_some())
+    }
+
+    #[test]
+    fn authenticate_rotated_secrets() {
+        let now = SystemTime::now();
+        let secrets = Secrets::new()
+            .with(Secret::new("old", "old-secret").expires_at(now + Duration::from_secs(60)))
+            .with(Secret::new("new", "new-secret"));
+        let authenticated = AuthenticateHook::new(secrets, |_: &Delivery| {});
+        let payload = r#"{"zen": "Approachable is better than simple."}"#;
+        let old = format!("sha256={}", sign(Sha256::new(), "old-secret", payload));
+        let new = format!("sha256={}", sign(Sha256::new(), "new-secret", payload));
+        let other = format!("sha256={}", sign(Sha256::new(), "other-secret", payload));
+        assert_eq!(
+            Some("old".to_owned()),
+            authenticated.authenticate(payload, None, Some(old.as_ref()))
+        );
+        assert_eq!(
+            Some("new".to_owned()),
+            authenticated.authenticate(payload, None, Some(new.as_ref()))
+        );
+        assert_eq!(
+            None,
+            authenticated.authenticate(payload, None, Some(other.as_ref()))
+        )
+    }
+
+    #[test]
+    fn reject_expired_secrets() {
+        let expired = Secret::new("old", "old-secret")
+            .expires_at(SystemTime::now() - Duration::from_secs(60));
+        let authenticated = AuthenticateHook::new(expired, |_: &Delivery| {});
+        let payload = r#"{"zen": "Approachable is better than simple."}"#;
+        let old = format!("sha256={}", sign(Sha256::new(), "old-secret", payload));
+        assert_eq!(
+            None,
+            authenticated.authenticate(payload, None, Some(old.as_ref()))
+        )
+    }
+
+    #[test]
+    fn authenticate_provided_secrets() {
+        let provider = MemorySecrets::new().with(
+            SecretScope::Repository("softprops/afterparty".to_owned()),
+            "repo-secret",
+        );
+        let authenticated = AuthenticateHook::with_provider(provider, |_: &Delivery| {});
+        let payload = r#"{"repository": {"full_name": "softprops/afterparty"}}"#;
+        let other = r#"{"repository": {"full_name": "softprops/hubcaps"}}"#;
+        let signature = format!("sha256={}", sign(Sha256::new(), "repo-secret", payload));
+        let other_signature = format!("sha256={}", sign(Sha256::new(), "repo-secret", other));
+        assert!(authenticated
+            .authenticate(payload, None, Some(signature.as_ref()))
+            .is_some());
+        assert!(authenticated
+            .authenticate(other, None, Some(other_signature.as_ref()))
+            .is_none())
     }
 }
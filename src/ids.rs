@@ -0,0 +1,259 @@
+//! Strongly typed ids, so that ids of different kinds of objects
+//! can not be mixed up, i.e. as keys of the same map
+
+use serde::de::Error as DeError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+macro_rules! numeric_id {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(pub i64);
+
+        impl From<i64> for $name {
+            fn from(id: i64) -> $name {
+                $name(id)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                serializer.serialize_i64(self.0)
+            }
+        }
+
+        impl Deserialize for $name {
+            fn deserialize<D>(deserializer: D) -> Result<$name, D::Error>
+            where
+                D: Deserializer,
+            {
+                i64::deserialize(deserializer).map($name)
+            }
+        }
+    };
+}
+
+numeric_id!(
+    /// The id of a user, bot or other account
+    UserId
+);
+
+numeric_id!(
+    /// The id of a repository
+    RepoId
+);
+
+numeric_id!(
+    /// The id of a github app installation
+    InstallationId
+);
+
+/// The global node id of an object in github's graphql api
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(pub String);
+
+impl NodeId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'a> From<&'a str> for NodeId {
+    fn from(id: &'a str) -> NodeId {
+        NodeId(id.to_owned())
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for NodeId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl Deserialize for NodeId {
+    fn deserialize<D>(deserializer: D) -> Result<NodeId, D::Error>
+    where
+        D: Deserializer,
+    {
+        String::deserialize(deserializer).map(NodeId)
+    }
+}
+
+/// The id github identifies a delivery with in its `X-Github-Delivery` header
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DeliveryId {
+    /// the GUID github sends, i.e. `72d3162e-cc78-11e3-81ab-4c9367dc0958`
+    Guid(u128),
+    /// any other id, as sent by manual redeliveries, test tooling or proxies
+    Other(String),
+}
+
+impl DeliveryId {
+    /// the GUID of ids which are one
+    pub fn as_u128(&self) -> Option<u128> {
+        match *self {
+            DeliveryId::Guid(guid) => Some(guid),
+            DeliveryId::Other(_) => None,
+        }
+    }
+}
+
+/// the longest delivery id which is not a GUID accepted
+const MAX_DELIVERY_ID_LEN: usize = 128;
+
+/// Parses GUIDs as such and keeps any other id of up to 128 printable
+/// ascii characters without whitespace as it is
+impl FromStr for DeliveryId {
+    type Err = DeliveryIdError;
+
+    fn from_str(id: &str) -> Result<DeliveryId, DeliveryIdError> {
+        if id.is_empty()
+            || id.len() > MAX_DELIVERY_ID_LEN
+            || !id.chars().all(|c| c.is_ascii_graphic())
+        {
+            return Err(DeliveryIdError(id.to_owned()));
+        }
+        let groups = id.split('-').map(|group| group.len()).collect::<Vec<_>>();
+        let hex = id.replace('-', "");
+        if groups != [8, 4, 4, 4, 12] || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(DeliveryId::Other(id.to_owned()));
+        }
+        Ok(u128::from_str_radix(&hex, 16)
+            .map(DeliveryId::Guid)
+            .unwrap_or_else(|_| DeliveryId::Other(id.to_owned())))
+    }
+}
+
+impl fmt::Display for DeliveryId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeliveryId::Guid(guid) => {
+                let hex = format!("{:032x}", guid);
+                write!(
+                    f,
+                    "{}-{}-{}-{}-{}",
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..]
+                )
+            }
+            DeliveryId::Other(ref id) => f.write_str(id),
+        }
+    }
+}
+
+impl Serialize for DeliveryId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl Deserialize for DeliveryId {
+    fn deserialize<D>(deserializer: D) -> Result<DeliveryId, D::Error>
+    where
+        D: Deserializer,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// A delivery id which is empty, too long or holds other characters than
+/// printable ascii without whitespace
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeliveryIdError(String);
+
+impl fmt::Display for DeliveryIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid delivery id {:?}", self.0)
+    }
+}
+
+impl Error for DeliveryIdError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn parse_delivery_ids() {
+        let id = "72d3162e-cc78-11e3-81ab-4c9367dc0958"
+            .parse::<DeliveryId>()
+            .unwrap();
+        assert_eq!("72d3162e-cc78-11e3-81ab-4c9367dc0958", id.to_string());
+        assert_eq!(id, "72D3162E-CC78-11E3-81AB-4C9367DC0958".parse().unwrap());
+        assert_eq!(Some(0x72d3162e_cc78_11e3_81ab_4c9367dc0958), id.as_u128());
+        assert_eq!(
+            Ok(DeliveryId::Other("redelivery-1".to_owned())),
+            "redelivery-1".parse::<DeliveryId>()
+        );
+        assert_eq!(
+            Ok(DeliveryId::Other(
+                "+2d3162e-cc78-11e3-81ab-4c9367dc0958".to_owned()
+            )),
+            "+2d3162e-cc78-11e3-81ab-4c9367dc0958".parse::<DeliveryId>()
+        );
+        assert_eq!(
+            "72d3162ecc7811e381ab4c9367dc0958",
+            "72d3162ecc7811e381ab4c9367dc0958"
+                .parse::<DeliveryId>()
+                .unwrap()
+                .to_string()
+        );
+        for invalid in &["", " ", "a b", "a\nb", "\u{7f}", "é"] {
+            assert_eq!(
+                Err(DeliveryIdError((*invalid).to_owned())),
+                invalid.parse::<DeliveryId>()
+            );
+        }
+        assert!("x".repeat(128).parse::<DeliveryId>().is_ok());
+        assert!("x".repeat(129).parse::<DeliveryId>().is_err());
+        assert_eq!(
+            "\"72d3162e-cc78-11e3-81ab-4c9367dc0958\"",
+            serde_json::to_string(&id).unwrap()
+        );
+    }
+
+    #[test]
+    fn ids_are_keys() {
+        let mut installations = HashMap::new();
+        installations.insert(InstallationId(1), "installation");
+        let repo: RepoId = serde_json::from_str("1").unwrap();
+        assert_eq!(RepoId(1), repo);
+        assert_eq!(Some(&"installation"), installations.get(&InstallationId(1)));
+        assert_eq!("1", serde_json::to_string(&UserId(1)).unwrap());
+        assert_eq!(
+            NodeId::from("MDQ6VXNlcjIxMDMxMDY3"),
+            serde_json::from_str("\"MDQ6VXNlcjIxMDMxMDY3\"").unwrap()
+        );
+    }
+}
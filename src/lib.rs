@@ -10,19 +10,84 @@ extern crate hex;
 extern crate serde;
 extern crate serde_json;
 
+#[cfg(feature = "async")]
+extern crate bytes;
+#[cfg(feature = "async")]
+extern crate futures_util;
+#[cfg(feature = "async")]
+extern crate http;
+#[cfg(feature = "async")]
+extern crate http_body_util;
+#[cfg(feature = "async")]
+extern crate hyper1;
+#[cfg(feature = "async")]
+extern crate hyper_util;
+#[cfg(feature = "async")]
+extern crate tokio;
+
+#[cfg(feature = "typed-fields")]
+extern crate chrono;
+#[cfg(feature = "typed-fields")]
+extern crate url;
+
+#[cfg(test)]
+extern crate proptest;
+
+mod allowlist;
+#[cfg(feature = "async")]
+mod async_hook;
+mod dispatch;
+mod error;
 mod events;
+mod fields;
+mod filter;
 mod hook;
+mod ids;
+mod metrics;
+mod secrets;
+mod signature;
+mod store;
+mod subscription;
+mod verify;
+
+#[cfg(feature = "async")]
+pub mod server;
 
-pub use events::Event;
-pub use hook::{AuthenticateHook, Hook};
+pub use allowlist::{Allowlist, Cidr, CidrError, ProxyHeader};
+#[cfg(feature = "async")]
+pub use async_hook::{AsyncHook, Blocking, HookFuture};
+pub use dispatch::{DispatchOutcome, HeaderSource, HookResult};
+pub use error::{DeliveryError, PayloadError};
+pub use events::{actions, AccountRef, Event, InstallationRef, OrganizationRef, RepositoryRef};
+pub use fields::{Timestamp, UnixTimestamp, Url};
+pub use filter::{
+    has_label, not_sender_type, only_branch, only_repo, And, Filter, Filtered, HasLabel,
+    NotSenderType, OnlyBranch, OnlyRepo, Or,
+};
+use hook::Infallible;
+pub use hook::{AuthenticateHook, FallibleHook, Hook, HookError, HookOutcome, Registered};
 use hyper::server::{Handler, Request, Response};
-use std::collections::HashMap;
+use hyper::status::StatusCode;
+pub use ids::{DeliveryId, DeliveryIdError, InstallationId, NodeId, RepoId, UserId};
+pub use metrics::Metrics;
+pub use secrets::{
+    EnvSecrets, FileSecrets, MemorySecrets, Secret, SecretContext, SecretProvider, SecretScope,
+    Secrets,
+};
+pub use signature::{Algorithm, Signature, SignatureError};
 use std::io::Read;
+pub use store::{DeliveryStore, FileStore, MemoryStore};
+use subscription::Hooks;
+pub use subscription::Subscription;
+pub use verify::{Verifier, VerifyError};
 
 // signature for request
 // see [this document](https://developer.github.com/webhooks/securing/) for more information
 header! {(XHubSignature, "X-Hub-Signature") => [String]}
 
+// sha256 signature for request, preferred by github over the sha1 based X-Hub-Signature
+header! {(XHubSignature256, "X-Hub-Signature-256") => [String]}
+
 // name of Github event
 // see [this document](https://developer.github.com/webhooks/#events) for available types
 header! {(XGithubEvent, "X-Github-Event") => [String]}
@@ -32,45 +97,79 @@ header! {(XGithubDelivery, "X-Github-Delivery") => [String]}
 
 // A delivery encodes all information about web hook request
 #[derive(Debug)]
-pub struct Delivery<'a> {
-    pub id: &'a str,
-    pub event: &'a str,
+pub struct Delivery {
+    pub id: DeliveryId,
+    pub event: String,
     pub payload: Event,
-    pub unparsed_payload: &'a str,
-    pub signature: Option<&'a str>,
+    pub unparsed_payload: String,
+    pub signature: Option<String>,
+    pub signature_256: Option<String>,
 }
 
-impl<'a> Delivery<'a> {
+impl Delivery {
     pub fn new(
-        id: &'a str,
-        event: &'a str,
-        payload: &'a str,
-        signature: Option<&'a str>,
-    ) -> Option<Delivery<'a>> {
-        // patching raw payload with camelized name field for enum deserialization
-        let patched = events::patch_payload_json(event, payload);
-        match serde_json::from_str::<Event>(&patched) {
-            Ok(parsed) => Some(Delivery {
-                id: id,
-                event: event,
-                payload: parsed,
-                unparsed_payload: payload,
-                signature: signature,
-            }),
-            Err(e) => {
-                // println!("{}", e);
-                // println!("failed to parse json {:?}\n{:#?}", e, patched);
-                error!("failed to parse json {:?}\n{:#?}", e, patched);
-                None
-            }
+        id: &str,
+        event: &str,
+        payload: &str,
+        signature: Option<&str>,
+        signature_256: Option<&str>,
+    ) -> Result<Delivery, DeliveryError> {
+        Delivery::with_id(id.parse()?, event, payload, signature, signature_256)
+    }
+
+    pub(crate) fn with_id(
+        id: DeliveryId,
+        event: &str,
+        payload: &str,
+        signature: Option<&str>,
+        signature_256: Option<&str>,
+    ) -> Result<Delivery, DeliveryError> {
+        Ok(Delivery::with_payload(
+            id,
+            event,
+            events::parse(event, payload)?,
+            payload,
+            signature,
+            signature_256,
+        ))
+    }
+
+    pub(crate) fn with_payload(
+        id: DeliveryId,
+        event: &str,
+        parsed: Event,
+        payload: &str,
+        signature: Option<&str>,
+        signature_256: Option<&str>,
+    ) -> Delivery {
+        if let Event::Unknown { .. } = parsed {
+            info!("delivery {} is of unknown event {:?}", id, event)
+        }
+        Delivery {
+            id,
+            event: event.to_owned(),
+            payload: parsed,
+            unparsed_payload: payload.to_owned(),
+            signature: signature.map(|s| s.to_owned()),
+            signature_256: signature_256.map(|s| s.to_owned()),
         }
     }
 }
 
+/// A callback passed the errors of deliveries which fail to parse
+type ErrorHook = Box<dyn Fn(&DeliveryError) + Send + Sync>;
+
 /// A hub is a registry of hooks
 #[derive(Default)]
 pub struct Hub {
-    hooks: HashMap<String, Vec<Box<Hook>>>,
+    hooks: Hooks<dyn FallibleHook>,
+    #[cfg(feature = "async")]
+    async_hooks: Hooks<dyn AsyncHook>,
+    verifier: Option<Verifier>,
+    deliveries: Option<Box<dyn DeliveryStore>>,
+    allowlist: Option<Allowlist>,
+    errors: Vec<ErrorHook>,
+    metrics: Metrics,
 }
 
 impl Hub {
@@ -81,85 +180,258 @@ impl Hub {
         }
     }
 
+    /// verifies the signature of every delivery before its payload
+    /// is parsed, rejecting deliveries which fail verification with
+    /// a `401 Unauthorized` response
+    pub fn verify_with(&mut self, verifier: Verifier) {
+        self.verifier = Some(verifier)
+    }
+
+    /// only permits deliveries from addresses within the allowlist,
+    /// rejecting others with a `403 Forbidden` response
+    pub fn allow_from(&mut self, allowlist: Allowlist) {
+        self.allowlist = Some(allowlist)
+    }
+
+    /// records the ids of dispatched deliveries, acknowledging but not
+    /// dispatching deliveries whose id was recorded before. This protects
+    /// hooks from replayed requests and github's own redeliveries
+    pub fn deduplicate_with<S>(&mut self, store: S)
+    where
+        S: DeliveryStore + 'static,
+    {
+        self.deliveries = Some(Box::new(store))
+    }
+
+    /// calls `hook` with the error of every delivery which fails to parse,
+    /// i.e. because github changed the payload of an event. Deliveries whose
+    /// payload does not match the model of their event are still dispatched,
+    /// as an `Event::Unparsed`
+    pub fn handle_errors<F>(&mut self, hook: F)
+    where
+        F: Fn(&DeliveryError) + Send + Sync + 'static,
+    {
+        self.errors.push(Box::new(hook))
+    }
+
+    /// counts of how this hub has responded to deliveries
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
     /// adds a new web hook which will only be applied
     /// when a delivery is revcieved with a valid
-    /// request signature based on the provided secret
-    pub fn handle_authenticated<H, S>(&mut self, event: &str, secret: S, hook: H)
+    /// request signature based on any of the provided secrets.
+    /// Deliveries without one are responded to with a
+    /// `401 Unauthorized` unless another hook handled them
+    ///
+    /// # Panics
+    ///
+    /// when a pattern's event or one of its actions is empty, i.e. `push.`
+    pub fn handle_authenticated<E, H, S>(&mut self, events: E, secrets: S, hook: H)
     where
+        E: Into<Subscription>,
         H: Hook + 'static,
-        S: Into<String>,
+        S: Into<Secrets>,
     {
-        self.handle(event, AuthenticateHook::new(secret, hook))
+        self.hooks.register(
+            events.into(),
+            None,
+            true,
+            Box::new(AuthenticateHook::new(secrets, hook)),
+        )
+    }
+
+    /// adds a new web hook which will only be applied
+    /// when a delivery is revcieved with a valid
+    /// request signature based on the secrets looked up
+    /// by the provided secret provider
+    ///
+    /// # Panics
+    ///
+    /// when a pattern's event or one of its actions is empty, i.e. `push.`
+    pub fn handle_authenticated_with<E, H, P>(&mut self, events: E, provider: P, hook: H)
+    where
+        E: Into<Subscription>,
+        H: Hook + 'static,
+        P: SecretProvider + 'static,
+    {
+        self.hooks.register(
+            events.into(),
+            None,
+            true,
+            Box::new(AuthenticateHook::with_provider(provider, hook)),
+        )
     }
 
     /// add a need hook to list of hooks
     /// interested in a given event
-    pub fn handle<H>(&mut self, event: &str, hook: H)
+    ///
+    /// Hooks may subscribe to a list of events, i.e.
+    /// `["push", "create", "delete"]`, and to globs of events,
+    /// i.e. `pull_request*` or `project_*`. They may subscribe to
+    /// only some of an event's actions by following its name with
+    /// `.` and `|` separated actions, i.e. `pull_request.opened` or
+    /// `issues.labeled|unlabeled`. Such hooks are required and logged
+    /// under the events and their position, i.e. `push#0`. Deliveries
+    /// are dispatched to hooks in the order they were registered
+    ///
+    /// # Panics
+    ///
+    /// when a pattern's event or one of its actions is empty, i.e. `push.`
+    pub fn handle<E, H>(&mut self, events: E, hook: H)
     where
+        E: Into<Subscription>,
         H: Hook + 'static,
     {
         self.hooks
-            .entry(event.to_owned())
-            .or_insert(vec![])
-            .push(Box::new(hook));
+            .register(events.into(), None, true, Box::new(Infallible(hook)))
+    }
+
+    /// add a fallible hook to the list of hooks interested in a given event,
+    /// logging its results under `name`. Deliveries which any required hook
+    /// fails to handle are responded to with a `500 Internal Server Error`
+    ///
+    /// # Panics
+    ///
+    /// when a pattern's event or one of its actions is empty, i.e. `push.`
+    pub fn handle_fallible<E, H>(&mut self, events: E, name: &str, hook: H)
+    where
+        E: Into<Subscription>,
+        H: FallibleHook + 'static,
+    {
+        self.hooks
+            .register(events.into(), Some(name), true, Box::new(hook))
+    }
+
+    /// add a fallible hook whose failures are logged under `name`
+    /// but do not fail the delivery
+    ///
+    /// # Panics
+    ///
+    /// when a pattern's event or one of its actions is empty, i.e. `push.`
+    pub fn handle_optional<E, H>(&mut self, events: E, name: &str, hook: H)
+    where
+        E: Into<Subscription>,
+        H: FallibleHook + 'static,
+    {
+        self.hooks
+            .register(events.into(), Some(name), false, Box::new(hook))
+    }
+
+    /// add an async hook to the list of hooks
+    /// interested in a given event. Async hooks are only
+    /// dispatched to by `dispatch_async`, i.e. when serving
+    /// a hub with the `server` module
+    ///
+    /// # Panics
+    ///
+    /// when a pattern's event or one of its actions is empty, i.e. `push.`
+    #[cfg(feature = "async")]
+    pub fn handle_async<E, H>(&mut self, events: E, hook: H)
+    where
+        E: Into<Subscription>,
+        H: AsyncHook + 'static,
+    {
+        self.async_hooks
+            .register(events.into(), None, true, Box::new(hook))
     }
 
     /// get all interested hooks for a given event
-    pub fn hooks(&self, event: &str) -> Option<Vec<&Box<Hook>>> {
-        let explicit = self.hooks.get(event);
-        let implicit = self.hooks.get("*");
-        let combined = match (explicit, implicit) {
-            (Some(ex), Some(im)) => {
-                Some(ex.iter().chain(im.iter()).into_iter().collect::<Vec<_>>())
-            }
-            (Some(ex), _) => Some(ex.into_iter().collect::<Vec<_>>()),
-            (_, Some(im)) => Some(im.into_iter().collect::<Vec<_>>()),
-            _ => None,
-        };
-        combined
+    pub fn hooks(&self, event: &str) -> Option<Vec<&Registered<dyn FallibleHook>>> {
+        self.hooks.interested(event)
+    }
+
+    /// get the hooks interested in a delivery of an event, taking
+    /// the payload's action into account. Payloads whose action can
+    /// not be read fail when any of the hooks subscribed to actions
+    pub fn subscribed_hooks(
+        &self,
+        event: &str,
+        payload: &str,
+    ) -> Result<Option<Vec<&Registered<dyn FallibleHook>>>, DeliveryError> {
+        self.hooks
+            .subscribed(event, || events::parse_action(event, payload))
+    }
+
+    /// get all interested async hooks for a given event
+    #[cfg(feature = "async")]
+    pub fn async_hooks(&self, event: &str) -> Option<Vec<&Registered<dyn AsyncHook>>> {
+        self.async_hooks.interested(event)
+    }
+
+    /// get the async hooks interested in a delivery of an event,
+    /// taking the payload's action into account
+    #[cfg(feature = "async")]
+    pub fn subscribed_async_hooks(
+        &self,
+        event: &str,
+        payload: &str,
+    ) -> Result<Option<Vec<&Registered<dyn AsyncHook>>>, DeliveryError> {
+        self.async_hooks
+            .subscribed(event, || events::parse_action(event, payload))
     }
 }
 
 impl Handler for Hub {
-    fn handle(&self, mut req: Request, res: Response) {
-        let headers = req.headers.clone();
-        if let (Some(&XGithubEvent(ref event)), Some(&XGithubDelivery(ref delivery))) = (
-            headers.get::<XGithubEvent>(),
-            headers.get::<XGithubDelivery>(),
-        ) {
-            let signature = headers.get::<XHubSignature>();
-            info!("recv '{}' event with signature '{:?}'", event, signature);
-            if let Some(hooks) = self.hooks(event) {
-                let mut payload = String::new();
-                if let Ok(_) = req.read_to_string(&mut payload) {
-                    match Delivery::new(
-                        delivery,
-                        event,
-                        payload.as_ref(),
-                        signature.map(|s| s.as_ref()),
-                    ) {
-                        Some(delivery) => {
-                            // println!("{:?}", delivery);
-                            for hook in hooks {
-                                hook.handle(&delivery);
-                            }
-                        }
-                        _ => error!(
-                            "failed to parse event {:?} for delivery {:?}",
-                            event, delivery
-                        ),
-                    }
+    fn handle(&self, mut req: Request, mut res: Response) {
+        let mut payload = vec![];
+        let outcome = match req.read_to_end(&mut payload) {
+            Ok(_) => self.dispatch_from(req.remote_addr.ip(), &req.headers, &payload),
+            Err(e) => {
+                error!("failed to read payload: {}", e);
+                DispatchOutcome {
+                    status: 400,
+                    body: "unreadable payload".to_owned(),
+                    hooks: vec![],
                 }
             }
-        }
-        let _ = res.send(b"ok");
-        ()
+        };
+        *res.status_mut() = StatusCode::from_u16(outcome.status);
+        let _ = res.send(outcome.body.as_bytes());
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Delivery, Hub};
+    use super::{
+        Allowlist, Cidr, Delivery, DeliveryError, DeliveryId, Event, HookError, HookOutcome,
+        HookResult, Hub, MemoryStore, Verifier, XGithubDelivery, XGithubEvent, XHubSignature256,
+    };
+    use crypto::hmac::Hmac;
+    use crypto::mac::Mac;
+    use crypto::sha2::Sha256;
+    use hex::ToHex;
+    use hyper::header::Headers;
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    const WATCH: &str = include_str!("../data/watch.json");
+
+    fn headers(event: &str, signature_256: Option<String>) -> Headers {
+        let mut headers = Headers::new();
+        headers.set(XGithubEvent(event.to_owned()));
+        headers.set(XGithubDelivery(
+            "72d3162e-cc78-11e3-81ab-4c9367dc0958".to_owned(),
+        ));
+        if let Some(signature) = signature_256 {
+            headers.set(XHubSignature256(signature));
+        }
+        headers
+    }
+
+    fn localhost() -> IpAddr {
+        "127.0.0.1".parse().unwrap()
+    }
+
+    fn sign(secret: &str, payload: &str) -> String {
+        let mut mac = Hmac::new(Sha256::new(), secret.as_bytes());
+        mac.input(payload.as_bytes());
+        let mut signature = String::new();
+        mac.result().code().write_hex(&mut signature).unwrap();
+        format!("sha256={}", signature)
+    }
 
     #[test]
     fn hub_hooks() {
@@ -174,4 +446,294 @@ mod tests {
             hub.hooks("push").map(|hooks| hooks.into_iter().count())
         )
     }
+
+    #[test]
+    fn hub_hooks_patterns() {
+        let mut hub = Hub::new();
+        hub.handle(["push", "create", "delete"], |_: &Delivery| {});
+        hub.handle("pull_request*", |_: &Delivery| {});
+        hub.handle(vec!["project_*", "push"], |_: &Delivery| {});
+        let count = |event| hub.hooks(event).map(|hooks| hooks.len()).unwrap_or(0);
+        assert_eq!(2, count("push"));
+        assert_eq!(1, count("delete"));
+        assert_eq!(1, count("pull_request_review"));
+        assert_eq!(1, count("project_card"));
+        assert_eq!(0, count("issues"));
+    }
+
+    #[test]
+    fn hub_accepts_deliveries() {
+        let mut hub = Hub::new();
+        hub.handle("watch", |_: &Delivery| {});
+        let status = hub
+            .dispatch_from(localhost(), &headers("watch", None), WATCH.as_bytes())
+            .status;
+        assert_eq!(202, status)
+    }
+
+    #[test]
+    fn hub_rejects_bad_requests() {
+        let mut hub = Hub::new();
+        hub.handle("watch", |_: &Delivery| {});
+        let status = hub
+            .dispatch_from(localhost(), &Headers::new(), WATCH.as_bytes())
+            .status;
+        assert_eq!(400, status);
+        let status = hub
+            .dispatch_from(localhost(), &headers("watch", None), b"{")
+            .status;
+        assert_eq!(400, status)
+    }
+
+    #[test]
+    #[should_panic(expected = "empty event or action")]
+    fn hub_rejects_empty_subscriptions() {
+        Hub::new().handle("pull_request.", |_: &Delivery| {})
+    }
+
+    #[test]
+    fn hub_verifies_signatures() {
+        let mut hub = Hub::new();
+        hub.verify_with(Verifier::new("secret"));
+        hub.handle("watch", |_: &Delivery| {});
+        let outcome = hub.dispatch_from(localhost(), &headers("watch", None), WATCH.as_bytes());
+        assert_eq!(401, outcome.status);
+        assert_eq!("unauthorized", outcome.body);
+        let outcome = hub.dispatch_from(
+            localhost(),
+            &headers("watch", Some(sign("other", WATCH))),
+            WATCH.as_bytes(),
+        );
+        assert_eq!(401, outcome.status);
+        assert_eq!("unauthorized", outcome.body);
+        let status = hub
+            .dispatch_from(
+                localhost(),
+                &headers("watch", Some(sign("secret", WATCH))),
+                WATCH.as_bytes(),
+            )
+            .status;
+        assert_eq!(202, status)
+    }
+
+    #[test]
+    fn hub_skips_duplicate_deliveries() {
+        let mut hub = Hub::new();
+        hub.deduplicate_with(MemoryStore::new(10));
+        let dispatched = Arc::new(AtomicUsize::new(0));
+        let counter = dispatched.clone();
+        hub.handle("watch", move |_: &Delivery| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let status = hub
+            .dispatch_from(localhost(), &headers("watch", None), WATCH.as_bytes())
+            .status;
+        assert_eq!(202, status);
+        let status = hub
+            .dispatch_from(localhost(), &headers("watch", None), WATCH.as_bytes())
+            .status;
+        assert_eq!(200, status);
+        assert_eq!(1, dispatched.load(Ordering::SeqCst));
+        assert_eq!(2, hub.metrics().received());
+        assert_eq!(1, hub.metrics().accepted());
+        assert_eq!(1, hub.metrics().duplicates())
+    }
+
+    #[test]
+    fn hub_dispatches_redeliveries_of_failed_deliveries() {
+        let mut hub = Hub::new();
+        hub.deduplicate_with(MemoryStore::new(10));
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        hub.handle_fallible("watch", "flaky", move |_: &Delivery| {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err(HookError::new("unavailable"))
+            } else {
+                Ok(HookOutcome::Handled)
+            }
+        });
+        let status = |hub: &Hub| {
+            hub.dispatch_from(localhost(), &headers("watch", None), WATCH.as_bytes())
+                .status
+        };
+        assert_eq!(500, status(&hub));
+        assert_eq!(202, status(&hub));
+        assert_eq!(200, status(&hub));
+        assert_eq!(2, attempts.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn hub_rejects_unauthenticated_deliveries() {
+        let mut hub = Hub::new();
+        let dispatched = Arc::new(AtomicUsize::new(0));
+        let counter = dispatched.clone();
+        hub.handle_authenticated("watch", "secret", move |_: &Delivery| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        hub.handle_authenticated("watch", "tenant", |_: &Delivery| {});
+        let unsigned = hub.dispatch_from(localhost(), &headers("watch", None), WATCH.as_bytes());
+        assert_eq!(401, unsigned.status);
+        assert_eq!(vec![HookResult::Rejected; 2], unsigned.hooks);
+        let status = hub
+            .dispatch_from(
+                localhost(),
+                &headers("watch", Some(sign("other", WATCH))),
+                WATCH.as_bytes(),
+            )
+            .status;
+        assert_eq!(401, status);
+        // hooks authenticating other tenants' deliveries do not fail them
+        let signed = hub.dispatch_from(
+            localhost(),
+            &headers("watch", Some(sign("secret", WATCH))),
+            WATCH.as_bytes(),
+        );
+        assert_eq!(202, signed.status);
+        assert_eq!(
+            vec![HookResult::Completed, HookResult::Rejected],
+            signed.hooks
+        );
+        assert_eq!(1, dispatched.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn hub_rejects_unlisted_peers() {
+        let mut hub = Hub::new();
+        hub.allow_from(
+            Allowlist::new(vec![Cidr::parse("192.30.252.0/22").unwrap()])
+                .trust_proxies(vec![Cidr::parse("127.0.0.1").unwrap()]),
+        );
+        hub.handle("watch", |_: &Delivery| {});
+        let status = hub
+            .dispatch_from(localhost(), &headers("watch", None), WATCH.as_bytes())
+            .status;
+        assert_eq!(403, status);
+        let mut forwarded = headers("watch", None);
+        forwarded.set_raw("X-Forwarded-For", vec![b"192.30.252.1".to_vec()]);
+        let status = hub
+            .dispatch_from(localhost(), &forwarded, WATCH.as_bytes())
+            .status;
+        assert_eq!(202, status)
+    }
+
+    #[test]
+    fn hub_dispatches_subscribed_actions() {
+        let mut hub = Hub::new();
+        let dispatched = Arc::new(AtomicUsize::new(0));
+        let counter = dispatched.clone();
+        hub.handle("watch.started", move |_: &Delivery| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let counter = dispatched.clone();
+        hub.handle("watch.deleted|edited", move |_: &Delivery| {
+            counter.fetch_add(10, Ordering::SeqCst);
+        });
+        let outcome = hub.dispatch_from(localhost(), &headers("watch", None), WATCH.as_bytes());
+        assert_eq!(202, outcome.status);
+        assert_eq!(1, outcome.hooks.len());
+        assert_eq!(1, dispatched.load(Ordering::SeqCst));
+        // payloads no hook subscribed to are not parsed
+        let status = hub
+            .dispatch_from(
+                localhost(),
+                &headers("watch", None),
+                br#"{"action": "stopped"}"#,
+            )
+            .status;
+        assert_eq!(202, status);
+        let status = hub
+            .dispatch_from(localhost(), &headers("watch", None), b"{\"action\": ")
+            .status;
+        assert_eq!(400, status);
+        assert_eq!(
+            vec!["watch.started#0", "watch.deleted|edited#1"],
+            hub.hooks("watch")
+                .unwrap()
+                .iter()
+                .map(|hook| hook.name())
+                .collect::<Vec<_>>()
+        )
+    }
+
+    #[test]
+    fn hub_dispatches_unknown_events() {
+        let mut hub = Hub::new();
+        let dispatched = Arc::new(AtomicUsize::new(0));
+        let counter = dispatched.clone();
+        hub.handle("*", move |delivery: &Delivery| {
+            assert_eq!("sponsorship", delivery.payload.name());
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let payload = br#"{"action": "created"}"#;
+        let outcome = hub.dispatch_from(localhost(), &headers("sponsorship", None), payload);
+        assert_eq!(202, outcome.status);
+        assert_eq!(1, dispatched.load(Ordering::SeqCst));
+        let outcome = hub.dispatch_from(localhost(), &headers("sponsorship", None), b"{");
+        assert_eq!(400, outcome.status);
+    }
+
+    #[test]
+    fn hub_reports_errors() {
+        let mut hub = Hub::new();
+        let errors = Arc::new(Mutex::new(vec![]));
+        let reported = errors.clone();
+        hub.handle_errors(move |error: &DeliveryError| {
+            reported.lock().unwrap().push(error.clone())
+        });
+        hub.handle("*", |delivery: &Delivery| match delivery.payload {
+            Event::Unparsed {
+                ref raw, ref path, ..
+            } => {
+                assert_eq!(Some("started"), raw["action"].as_str());
+                assert_eq!("repository.owner.login", path);
+            }
+            _ => panic!("expected an unparsed event"),
+        });
+        let payload = WATCH.replace("\"login\": \"Codertocat\"", "\"login\": 1");
+        let outcome = hub.dispatch_from(localhost(), &headers("watch", None), payload.as_bytes());
+        assert_eq!(202, outcome.status);
+        assert_eq!(vec![HookResult::Completed], outcome.hooks);
+        let outcome = hub.dispatch_from(localhost(), &headers("watch", None), b"[");
+        assert_eq!(400, outcome.status);
+        let invalid = [("X-GitHub-Event", "watch"), ("X-GitHub-Delivery", "")];
+        let outcome = hub.dispatch_from(localhost(), &invalid[..], WATCH.as_bytes());
+        assert_eq!(400, outcome.status);
+        let errors = errors.lock().unwrap();
+        assert_eq!(2, errors.len());
+        match errors[0] {
+            DeliveryError::Mismatch(ref e) => {
+                assert_eq!("watch", e.event);
+                assert_eq!("repository.owner.login", e.path);
+                assert_eq!("a string", e.expected);
+                assert_eq!("1", e.found);
+            }
+            ref other => panic!("expected a mismatch, got {:?}", other),
+        }
+        match errors[1] {
+            DeliveryError::InvalidJson { ref event, .. } => assert_eq!("watch", event),
+            ref other => panic!("expected invalid json, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn delivery_errors() {
+        assert_eq!(
+            Err(DeliveryError::InvalidId(
+                "".parse::<DeliveryId>().unwrap_err()
+            )),
+            Delivery::new("", "watch", WATCH, None, None).map(|d| d.id)
+        );
+        assert_eq!(
+            Ok(DeliveryId::Other("1".to_owned())),
+            Delivery::new("1", "watch", WATCH, None, None).map(|d| d.id)
+        );
+        assert!(Delivery::new(
+            "72d3162e-cc78-11e3-81ab-4c9367dc0958",
+            "watch",
+            WATCH,
+            None,
+            None
+        )
+        .is_ok());
+    }
 }
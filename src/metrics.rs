@@ -0,0 +1,61 @@
+//! Counters of deliveries a hub has received
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Running counts of how a hub has responded to deliveries
+#[derive(Debug, Default)]
+pub struct Metrics {
+    received: AtomicUsize,
+    accepted: AtomicUsize,
+    rejected: AtomicUsize,
+    duplicates: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+impl Metrics {
+    /// deliveries received, regardless of their outcome
+    pub fn received(&self) -> usize {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// deliveries which were accepted and dispatched to hooks
+    pub fn accepted(&self) -> usize {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    /// deliveries which were rejected, i.e. failed verification or parsing
+    pub fn rejected(&self) -> usize {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// deliveries which were acknowledged but not dispatched because
+    /// their id was seen before
+    pub fn duplicates(&self) -> usize {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+
+    /// deliveries which were dispatched but failed to be handled by hooks
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn count_received(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_accepted(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_duplicate(&self) {
+        self.duplicates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,616 @@
+//! Webhook secrets used to authenticate deliveries
+
+use serde_json;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// name given to secrets which were provided without one
+pub const DEFAULT_SECRET_NAME: &str = "default";
+
+/// A named webhook secret which may optionally expire
+#[derive(Clone)]
+pub struct Secret {
+    name: String,
+    value: String,
+    expires: Option<SystemTime>,
+}
+
+impl Secret {
+    pub fn new<N, V>(name: N, value: V) -> Secret
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        Secret {
+            name: name.into(),
+            value: value.into(),
+            expires: None,
+        }
+    }
+
+    /// marks this secret as no longer accepted after the given point in time
+    pub fn expires_at(self, expires: SystemTime) -> Secret {
+        Secret {
+            expires: Some(expires),
+            ..self
+        }
+    }
+
+    /// the name used to report which secret authenticated a delivery
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.expires
+    }
+
+    /// returns true if this secret has not yet expired at `now`
+    pub fn is_live(&self, now: SystemTime) -> bool {
+        self.expires.map(|expires| now < expires).unwrap_or(true)
+    }
+}
+
+/// Secrets are debugged by name, never by value
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Secret")
+            .field("name", &self.name)
+            .field("value", &"<redacted>")
+            .field("expires", &self.expires)
+            .finish()
+    }
+}
+
+/// A set of secrets which are all accepted at the same time,
+/// typically the old and the new secret while rotating
+#[derive(Clone, Debug, Default)]
+pub struct Secrets {
+    secrets: Vec<Secret>,
+}
+
+impl Secrets {
+    pub fn new() -> Secrets {
+        Secrets {
+            ..Default::default()
+        }
+    }
+
+    /// adds a secret to this set
+    pub fn with(mut self, secret: Secret) -> Secrets {
+        self.add(secret);
+        self
+    }
+
+    /// adds a secret to this set
+    pub fn add(&mut self, secret: Secret) {
+        self.secrets.push(secret)
+    }
+
+    /// all secrets, including expired ones
+    pub fn all(&self) -> &[Secret] {
+        &self.secrets
+    }
+
+    /// secrets which have not yet expired at `now`
+    pub fn live<'a>(&'a self, now: SystemTime) -> impl Iterator<Item = &'a Secret> + 'a {
+        self.secrets
+            .iter()
+            .filter(move |secret| secret.is_live(now))
+    }
+}
+
+impl From<Secret> for Secrets {
+    fn from(secret: Secret) -> Secrets {
+        Secrets::new().with(secret)
+    }
+}
+
+impl From<Vec<Secret>> for Secrets {
+    fn from(secrets: Vec<Secret>) -> Secrets {
+        Secrets { secrets }
+    }
+}
+
+impl From<String> for Secrets {
+    fn from(value: String) -> Secrets {
+        Secret::new(DEFAULT_SECRET_NAME, value).into()
+    }
+}
+
+impl<'a> From<&'a str> for Secrets {
+    fn from(value: &'a str) -> Secrets {
+        Secret::new(DEFAULT_SECRET_NAME, value).into()
+    }
+}
+
+/// The scopes a delivery may be associated with, used to look up
+/// the secret it was signed with
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum SecretScope {
+    /// a repository full name, i.e. `owner/name`
+    Repository(String),
+    /// an organization login
+    Organization(String),
+    /// a github app installation id
+    Installation(i64),
+}
+
+/// Information about a delivery which secret providers use to look up secrets
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SecretContext {
+    pub repository: Option<String>,
+    pub organization: Option<String>,
+    pub installation: Option<i64>,
+}
+
+impl SecretContext {
+    /// extracts context from a raw, not yet authenticated, json payload
+    pub fn from_payload(payload: &str) -> SecretContext {
+        let json = match serde_json::from_str::<serde_json::Value>(payload) {
+            Ok(json) => json,
+            Err(_) => return SecretContext::default(),
+        };
+        let field = |object: &str, field: &str| json.get(object).and_then(|o| o.get(field));
+        SecretContext {
+            repository: field("repository", "full_name")
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_owned()),
+            organization: field("organization", "login")
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_owned()),
+            installation: field("installation", "id").and_then(|v| v.as_i64()),
+        }
+    }
+
+    /// whether the payload's repository belongs to the organization it names,
+    /// if it names both. A payload could otherwise name another tenant's
+    /// repository and be signed with its own organization's secret
+    pub fn is_consistent(&self) -> bool {
+        match (self.repository.as_ref(), self.organization.as_ref()) {
+            (Some(repository), Some(organization)) => repository
+                .split('/')
+                .next()
+                .map(|owner| owner.eq_ignore_ascii_case(organization))
+                .unwrap_or(false),
+            _ => true,
+        }
+    }
+
+    /// scopes of this context, most specific first. Payloads which name no
+    /// organization are scoped to the account owning their repository. Only
+    /// payloads which name neither are scoped to their installation, which
+    /// is not bound to a repository: a payload could otherwise name another
+    /// tenant's repository and be signed with its own installation's secret
+    pub fn scopes(&self) -> Vec<SecretScope> {
+        let mut scopes = vec![];
+        if let Some(ref repository) = self.repository {
+            scopes.push(SecretScope::Repository(repository.clone()))
+        }
+        let owner = self
+            .repository
+            .as_ref()
+            .and_then(|repository| repository.split('/').next());
+        if let Some(organization) = self.organization.as_deref().or(owner) {
+            scopes.push(SecretScope::Organization(organization.to_owned()))
+        }
+        if let (true, Some(installation)) = (scopes.is_empty(), self.installation) {
+            scopes.push(SecretScope::Installation(installation))
+        }
+        scopes
+    }
+}
+
+/// Looks up the secrets a delivery may be signed with
+///
+/// Scopes are read from the payload before it is authenticated, so a
+/// delivery is verified with the secrets of its most specific scope which
+/// has any. Scopes the payload implies without secrets fall back to the
+/// default secrets, never to a less specific scope it names
+pub trait SecretProvider: Send + Sync {
+    /// returns the secrets of each scope of a delivery with the given context
+    /// which has any, most specific first, or the default secrets when none has
+    fn secrets(&self, context: &SecretContext) -> Vec<Secrets>;
+}
+
+/// A fixed set of secrets is used for every delivery
+impl SecretProvider for Secrets {
+    fn secrets(&self, _: &SecretContext) -> Vec<Secrets> {
+        vec![self.clone()]
+    }
+}
+
+// the secrets of scopes which have any, or the fallback when none has
+fn scoped_or<I>(scoped: I, fallback: Option<Secrets>) -> Vec<Secrets>
+where
+    I: Iterator<Item = Secrets>,
+{
+    let scoped = scoped.collect::<Vec<_>>();
+    if scoped.is_empty() {
+        fallback.into_iter().collect()
+    } else {
+        scoped
+    }
+}
+
+/// An in-memory provider of secrets per repository, organization or installation
+///
+/// Deliveries are checked against the secrets of each of their scopes
+/// which has any, falling back to the default secrets when none has
+#[derive(Clone, Debug, Default)]
+pub struct MemorySecrets {
+    scoped: HashMap<SecretScope, Secrets>,
+    fallback: Option<Secrets>,
+}
+
+impl MemorySecrets {
+    pub fn new() -> MemorySecrets {
+        MemorySecrets {
+            ..Default::default()
+        }
+    }
+
+    /// registers secrets for the given scope
+    pub fn with<S>(mut self, scope: SecretScope, secrets: S) -> MemorySecrets
+    where
+        S: Into<Secrets>,
+    {
+        self.scoped.insert(scope, secrets.into());
+        self
+    }
+
+    /// registers secrets used when no scope matches
+    pub fn fallback<S>(self, secrets: S) -> MemorySecrets
+    where
+        S: Into<Secrets>,
+    {
+        MemorySecrets {
+            fallback: Some(secrets.into()),
+            ..self
+        }
+    }
+}
+
+impl SecretProvider for MemorySecrets {
+    fn secrets(&self, context: &SecretContext) -> Vec<Secrets> {
+        scoped_or(
+            context
+                .scopes()
+                .iter()
+                .filter_map(|scope| self.scoped.get(scope))
+                .cloned(),
+            self.fallback.clone(),
+        )
+    }
+}
+
+/// A provider of secrets stored in environment variables
+///
+/// Variables are named after the prefix and the delivery's scope, i.e.
+/// `{PREFIX}_REPO_{OWNER}_{NAME}`, `{PREFIX}_ORG_{LOGIN}` or
+/// `{PREFIX}_INSTALLATION_{ID}`, with any character which is not
+/// alphanumeric replaced by `_`. The `{PREFIX}` variable itself is used
+/// when no scoped variable is set. Secrets are named after their variable.
+#[derive(Clone, Debug)]
+pub struct EnvSecrets {
+    prefix: String,
+}
+
+impl EnvSecrets {
+    pub fn new<P>(prefix: P) -> EnvSecrets
+    where
+        P: Into<String>,
+    {
+        EnvSecrets {
+            prefix: prefix.into(),
+        }
+    }
+
+    /// the name of the variable holding secrets for a given scope
+    pub fn var(&self, scope: &SecretScope) -> String {
+        let suffix = match *scope {
+            SecretScope::Repository(ref name) => format!("REPO_{}", name),
+            SecretScope::Organization(ref login) => format!("ORG_{}", login),
+            SecretScope::Installation(id) => format!("INSTALLATION_{}", id),
+        };
+        let suffix = suffix
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        format!("{}_{}", self.prefix, suffix)
+    }
+}
+
+impl SecretProvider for EnvSecrets {
+    fn secrets(&self, context: &SecretContext) -> Vec<Secrets> {
+        let read = |var: String| {
+            env::var(&var)
+                .ok()
+                .map(|value| Secrets::from(Secret::new(var, value)))
+        };
+        scoped_or(
+            context
+                .scopes()
+                .iter()
+                .filter_map(|scope| read(self.var(scope))),
+            read(self.prefix.clone()),
+        )
+    }
+}
+
+/// A provider of secrets stored in files below a directory
+///
+/// Secrets are read from `repos/{owner}/{name}`, `orgs/{login}`,
+/// `installations/{id}` or `default`, relative to the directory, each
+/// non-empty line holding one secret. Files are read for every lookup
+/// so secrets may be rotated without restarting. Secrets are named after
+/// their file and line number, i.e. `orgs/github:2`.
+#[derive(Clone, Debug)]
+pub struct FileSecrets {
+    dir: PathBuf,
+}
+
+impl FileSecrets {
+    pub fn new<P>(dir: P) -> FileSecrets
+    where
+        P: Into<PathBuf>,
+    {
+        FileSecrets { dir: dir.into() }
+    }
+
+    /// the path of the file holding secrets for a given scope, relative
+    /// to the provider's directory. Scopes whose names could escape the
+    /// directory have no path
+    pub fn path(&self, scope: &SecretScope) -> Option<String> {
+        // scopes are extracted from not yet authenticated payloads
+        fn safe(name: &str) -> bool {
+            !name.is_empty()
+                && name != "."
+                && name != ".."
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        }
+        match *scope {
+            SecretScope::Repository(ref name) => {
+                let mut parts = name.splitn(2, '/');
+                match (parts.next(), parts.next()) {
+                    (Some(owner), Some(repo)) if safe(owner) && safe(repo) => {
+                        Some(format!("repos/{}/{}", owner, repo))
+                    }
+                    _ => None,
+                }
+            }
+            SecretScope::Organization(ref login) if safe(login) => Some(format!("orgs/{}", login)),
+            SecretScope::Organization(_) => None,
+            SecretScope::Installation(id) => Some(format!("installations/{}", id)),
+        }
+    }
+
+    fn read(&self, path: &str) -> Option<Secrets> {
+        let contents = fs::read_to_string(self.dir.join(path)).ok()?;
+        let secrets = contents
+            .lines()
+            .enumerate()
+            .map(|(idx, line)| (idx, line.trim()))
+            .filter(|&(_, line)| !line.is_empty())
+            .map(|(idx, line)| Secret::new(format!("{}:{}", path, idx + 1), line))
+            .collect::<Vec<_>>();
+        if secrets.is_empty() {
+            None
+        } else {
+            Some(secrets.into())
+        }
+    }
+}
+
+impl SecretProvider for FileSecrets {
+    fn secrets(&self, context: &SecretContext) -> Vec<Secrets> {
+        scoped_or(
+            context
+                .scopes()
+                .iter()
+                .filter_map(|scope| self.path(scope))
+                .filter_map(|path| self.read(&path)),
+            self.read("default"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn context() -> SecretContext {
+        SecretContext::from_payload(
+            r#"{
+              "repository": { "full_name": "softprops/afterparty" },
+              "organization": { "login": "softprops" },
+              "installation": { "id": 42 }
+            }"#,
+        )
+    }
+
+    #[test]
+    fn live_secrets() {
+        let now = SystemTime::now();
+        let secrets = Secrets::new()
+            .with(Secret::new("old", "a").expires_at(now - Duration::from_secs(1)))
+            .with(Secret::new("new", "b"));
+        assert_eq!(
+            vec!["new"],
+            secrets.live(now).map(|s| s.name()).collect::<Vec<_>>()
+        );
+        assert_eq!(2, secrets.all().len())
+    }
+
+    #[test]
+    fn default_secret_name() {
+        let secrets = Secrets::from("secret");
+        assert_eq!(DEFAULT_SECRET_NAME, secrets.all()[0].name())
+    }
+
+    #[test]
+    fn context_from_payload() {
+        assert_eq!(
+            SecretContext {
+                repository: Some("softprops/afterparty".to_owned()),
+                organization: Some("softprops".to_owned()),
+                installation: Some(42),
+            },
+            context()
+        );
+        assert_eq!(SecretContext::default(), SecretContext::from_payload("{"))
+    }
+
+    fn values(secrets: &[Secrets]) -> Vec<&str> {
+        secrets
+            .iter()
+            .flat_map(|secrets| secrets.all())
+            .map(|secret| secret.value())
+            .collect()
+    }
+
+    #[test]
+    fn debug_redacts_secrets() {
+        let debugged = format!("{:?}", Secrets::from(Secret::new("rotated", "hunter2")));
+        assert!(debugged.contains("rotated"));
+        assert!(!debugged.contains("hunter2"));
+    }
+
+    #[test]
+    fn context_consistency() {
+        assert!(context().is_consistent());
+        assert!(SecretContext::from_payload(
+            r#"{"repository": { "full_name": "softprops/afterparty" }}"#
+        )
+        .is_consistent());
+        assert!(!SecretContext::from_payload(
+            r#"{
+              "repository": { "full_name": "victim/app" },
+              "organization": { "login": "attacker" }
+            }"#
+        )
+        .is_consistent());
+    }
+
+    #[test]
+    fn context_scopes() {
+        assert_eq!(
+            vec![
+                SecretScope::Repository("softprops/afterparty".to_owned()),
+                SecretScope::Organization("softprops".to_owned()),
+            ],
+            context().scopes()
+        );
+        assert_eq!(
+            vec![
+                SecretScope::Repository("softprops/afterparty".to_owned()),
+                SecretScope::Organization("softprops".to_owned()),
+            ],
+            SecretContext::from_payload(
+                r#"{
+                  "repository": { "full_name": "softprops/afterparty" },
+                  "installation": { "id": 42 }
+                }"#
+            )
+            .scopes()
+        );
+        assert_eq!(
+            vec![SecretScope::Installation(42)],
+            SecretContext::from_payload(r#"{"installation": { "id": 42 }}"#).scopes()
+        );
+    }
+
+    #[test]
+    fn memory_secrets_of_scopes() {
+        let provider = MemorySecrets::new()
+            .with(SecretScope::Organization("softprops".to_owned()), "org")
+            .with(SecretScope::Installation(42), "installation")
+            .fallback("fallback");
+        assert_eq!(vec!["org"], values(&provider.secrets(&context())));
+        assert_eq!(
+            vec!["installation"],
+            values(&provider.secrets(&SecretContext {
+                installation: Some(42),
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            vec!["fallback"],
+            values(&provider.secrets(&SecretContext::default()))
+        )
+    }
+
+    #[test]
+    fn env_secrets() {
+        // variables are unique to this test, which may run alongside others
+        let provider = EnvSecrets::new("AFTERPARTY_TEST_ENV_SECRETS");
+        let var = "AFTERPARTY_TEST_ENV_SECRETS_ORG_SOFTPROPS";
+        assert_eq!(
+            "AFTERPARTY_TEST_ENV_SECRETS_REPO_SOFTPROPS_AFTERPARTY",
+            provider.var(&SecretScope::Repository("softprops/afterparty".to_owned()))
+        );
+        assert!(provider.secrets(&context()).is_empty());
+        env::set_var(var, "org");
+        let secrets = provider.secrets(&context());
+        env::remove_var(var);
+        assert_eq!(var, secrets[0].all()[0].name());
+        assert_eq!(vec!["org"], values(&secrets))
+    }
+
+    #[test]
+    fn file_secrets() {
+        let dir = env::temp_dir().join(format!("afterparty-secrets-{}", std::process::id()));
+        fs::create_dir_all(dir.join("repos/softprops")).unwrap();
+        fs::write(dir.join("repos/softprops/afterparty"), "old\n\nnew\n").unwrap();
+        fs::write(dir.join("default"), "default").unwrap();
+        let provider = FileSecrets::new(dir.clone());
+        let secrets = provider.secrets(&context());
+        assert_eq!(1, secrets.len());
+        assert_eq!(
+            vec![
+                ("repos/softprops/afterparty:1", "old"),
+                ("repos/softprops/afterparty:3", "new"),
+            ],
+            secrets[0]
+                .all()
+                .iter()
+                .map(|s| (s.name(), s.value()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["default"],
+            values(&provider.secrets(&SecretContext::default()))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_secrets_stay_within_dir() {
+        let provider = FileSecrets::new("/secrets");
+        assert_eq!(
+            None,
+            provider.path(&SecretScope::Repository("../etc/passwd".to_owned()))
+        );
+        assert_eq!(
+            None,
+            provider.path(&SecretScope::Organization("..".to_owned()))
+        );
+    }
+}
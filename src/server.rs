@@ -0,0 +1,304 @@
+//! A tokio based server for hubs with async hooks
+//!
+//! Requests are read and responded to asynchronously, synchronous
+//! hooks are run on tokio's blocking thread pool
+
+use super::Hub;
+use bytes::Bytes;
+use dispatch::DispatchOutcome;
+use futures_util::future::{self, Either};
+use futures_util::FutureExt;
+use http::{Request, Response, StatusCode};
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper1::body::Incoming;
+use hyper1::server::conn::http1;
+use hyper1::service::service_fn;
+use hyper_util::rt::{TokioIo, TokioTimer};
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::{self, IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::time::{self, Sleep};
+
+/// the largest payload github sends, 25 MB
+pub const DEFAULT_MAX_PAYLOAD: usize = 25 * 1024 * 1024;
+
+/// how long a client may take to send a request's headers, and then its body
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// how long to wait before accepting connections again after
+/// failing to, i.e. when the process ran out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A future which accepts connections and dispatches their
+/// deliveries to a hub until its listener is closed
+pub struct Server {
+    listener: TcpListener,
+    hub: Arc<Hub>,
+    max_payload: usize,
+    read_timeout: Duration,
+    backoff: Option<Pin<Box<Sleep>>>,
+}
+
+impl Server {
+    /// binds a server for `hub` to `addr`. This must be called within a tokio runtime
+    pub fn bind(addr: SocketAddr, hub: Hub) -> io::Result<Server> {
+        let listener = net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Server {
+            listener: TcpListener::from_std(listener)?,
+            hub: Arc::new(hub),
+            max_payload: DEFAULT_MAX_PAYLOAD,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            backoff: None,
+        })
+    }
+
+    /// rejects payloads larger than `bytes` with a `413 Payload Too Large`
+    /// response, `DEFAULT_MAX_PAYLOAD` by default
+    pub fn max_payload(self, bytes: usize) -> Server {
+        Server {
+            max_payload: bytes,
+            ..self
+        }
+    }
+
+    /// closes connections which do not send a request's headers within
+    /// `timeout`, and rejects requests which then do not send their body
+    /// within `timeout` with a `408 Request Timeout` response,
+    /// `DEFAULT_READ_TIMEOUT` by default
+    pub fn read_timeout(self, timeout: Duration) -> Server {
+        Server {
+            read_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// the address this server is listening on
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// the hub this server dispatches deliveries to
+    pub fn hub(&self) -> &Arc<Hub> {
+        &self.hub
+    }
+}
+
+impl Future for Server {
+    type Output = io::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let server = self.get_mut();
+        loop {
+            if let Some(ref mut backoff) = server.backoff {
+                if backoff.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+            }
+            server.backoff = None;
+            let (stream, peer) = match server.listener.poll_accept(cx) {
+                Poll::Ready(Ok(accepted)) => accepted,
+                Poll::Ready(Err(ref e)) if is_transient(e) => {
+                    warn!("failed to accept connection: {}", e);
+                    continue;
+                }
+                Poll::Ready(Err(e)) if is_closed(&e) => return Poll::Ready(Err(e)),
+                Poll::Ready(Err(e)) => {
+                    error!(
+                        "failed to accept connection, retrying in {:?}: {}",
+                        ACCEPT_BACKOFF, e
+                    );
+                    server.backoff = Some(Box::pin(time::sleep(ACCEPT_BACKOFF)));
+                    continue;
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            let hub = server.hub.clone();
+            let (max_payload, read_timeout) = (server.max_payload, server.read_timeout);
+            let service = service_fn(move |req| {
+                respond(hub.clone(), max_payload, read_timeout, peer.ip(), req)
+            });
+            let connection = http1::Builder::new()
+                .timer(TokioTimer::new())
+                .header_read_timeout(read_timeout)
+                .serve_connection(TokioIo::new(stream), service)
+                .map(move |result| {
+                    if let Err(e) = result {
+                        error!("failed to serve connection from {}: {}", peer, e)
+                    }
+                });
+            tokio::spawn(connection);
+        }
+    }
+}
+
+// errors which concern a single connection rather than the listener
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+    )
+}
+
+// errors of a listener which no longer listens, any other error,
+// i.e. running out of file descriptors, may pass
+fn is_closed(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::InvalidInput | io::ErrorKind::NotConnected
+    )
+}
+
+fn respond(
+    hub: Arc<Hub>,
+    max_payload: usize,
+    read_timeout: Duration,
+    peer: IpAddr,
+    req: Request<Incoming>,
+) -> impl Future<Output = Result<Response<Full<Bytes>>, Infallible>> {
+    let (parts, body) = req.into_parts();
+    time::timeout(read_timeout, Limited::new(body, max_payload).collect())
+        .then(move |collected| match collected {
+            Ok(Ok(collected)) => {
+                Either::Left(hub.dispatch_async(Some(peer), parts.headers, collected.to_bytes()))
+            }
+            Ok(Err(ref e)) if e.is::<LengthLimitError>() => {
+                warn!("rejecting payload larger than {} bytes", max_payload);
+                Either::Right(future::ready(DispatchOutcome::new(
+                    413,
+                    "payload too large",
+                )))
+            }
+            Ok(Err(e)) => {
+                error!("failed to read payload: {}", e);
+                Either::Right(future::ready(DispatchOutcome::new(
+                    400,
+                    "unreadable payload",
+                )))
+            }
+            Err(_) => {
+                warn!("rejecting payload not read within {:?}", read_timeout);
+                Either::Right(future::ready(DispatchOutcome::new(408, "request timeout")))
+            }
+        })
+        .map(|outcome| {
+            let mut response = Response::new(Full::new(Bytes::from(outcome.body)));
+            *response.status_mut() =
+                StatusCode::from_u16(outcome.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            Ok(response)
+        })
+}
+
+/// serves `hub` on `addr` from a new multi threaded tokio runtime,
+/// blocking until the server fails
+pub fn serve(addr: SocketAddr, hub: Hub) -> io::Result<()> {
+    let runtime = Runtime::new()?;
+    let server = {
+        let _entered = runtime.enter();
+        Server::bind(addr, hub)?
+    };
+    info!("listening on {}", server.local_addr()?);
+    runtime.block_on(server)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::thread;
+    use Delivery;
+
+    const WATCH: &str = include_str!("../data/watch.json");
+
+    fn bind(runtime: &Runtime, max_payload: usize) -> Server {
+        let _entered = runtime.enter();
+        let mut hub = Hub::new();
+        hub.handle_async("watch", |_: Arc<Delivery>| future::ready(()));
+        Server::bind("127.0.0.1:0".parse().unwrap(), hub)
+            .unwrap()
+            .max_payload(max_payload)
+    }
+
+    fn post(addr: SocketAddr, payload: &'static str) -> String {
+        send(addr, payload.len(), payload)
+    }
+
+    // sends a request claiming a body of `len` bytes, of which only `payload` is sent
+    fn send(addr: SocketAddr, len: usize, payload: &'static str) -> String {
+        thread::spawn(move || {
+            let mut stream = net::TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "POST / HTTP/1.1\r\nHost: {}\r\nX-GitHub-Event: watch\r\n\
+                 X-GitHub-Delivery: 72d3162e-cc78-11e3-81ab-4c9367dc0958\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                addr, len, payload
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+        .join()
+        .unwrap()
+    }
+
+    #[test]
+    fn serve_deliveries() {
+        let runtime = Runtime::new().unwrap();
+        let server = bind(&runtime, DEFAULT_MAX_PAYLOAD);
+        let addr = server.local_addr().unwrap();
+        let hub = server.hub().clone();
+        runtime.spawn(server);
+        let response = post(addr, WATCH);
+        assert!(response.starts_with("HTTP/1.1 202"), "{}", response);
+        assert!(response.ends_with("accepted"), "{}", response);
+        assert_eq!(1, hub.metrics().accepted())
+    }
+
+    #[test]
+    fn reject_large_payloads() {
+        let runtime = Runtime::new().unwrap();
+        let server = bind(&runtime, 16);
+        let addr = server.local_addr().unwrap();
+        let hub = server.hub().clone();
+        runtime.spawn(server);
+        let response = post(addr, WATCH);
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+        assert!(response.ends_with("payload too large"), "{}", response);
+        assert_eq!(0, hub.metrics().accepted())
+    }
+
+    #[test]
+    fn time_out_slow_clients() {
+        let runtime = Runtime::new().unwrap();
+        let server = bind(&runtime, DEFAULT_MAX_PAYLOAD).read_timeout(Duration::from_millis(100));
+        let addr = server.local_addr().unwrap();
+        runtime.spawn(server);
+        let response = send(addr, WATCH.len(), "{");
+        assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+        assert!(response.ends_with("request timeout"), "{}", response);
+        let headers = thread::spawn(move || {
+            let mut stream = net::TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            write!(stream, "POST / HTTP/1.1\r\nHost: {}\r\n", addr).unwrap();
+            let mut response = vec![];
+            stream.read_to_end(&mut response).map(|_| response)
+        })
+        .join()
+        .unwrap();
+        // the connection is closed rather than left waiting for the rest of the headers
+        assert!(headers.is_ok(), "{:?}", headers);
+    }
+}
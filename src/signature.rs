@@ -0,0 +1,193 @@
+//! Parsing of `X-Hub-Signature` and `X-Hub-Signature-256` header values
+//!
+//! Header values are attacker controlled so parsing never panics,
+//! regardless of their length or encoding
+
+use hex::FromHex;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// The HMAC digest algorithms github signs deliveries with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+}
+
+impl Algorithm {
+    /// the name used as prefix of signature header values
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Algorithm::Sha1 => "sha1",
+            Algorithm::Sha256 => "sha256",
+        }
+    }
+
+    /// the length of this algorithm's digests in bytes
+    pub fn digest_len(&self) -> usize {
+        match *self {
+            Algorithm::Sha1 => 20,
+            Algorithm::Sha256 => 32,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Algorithm> {
+        match name {
+            "sha1" => Some(Algorithm::Sha1),
+            "sha256" => Some(Algorithm::Sha256),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Reasons a signature header value may fail to parse
+#[derive(Debug, PartialEq)]
+pub enum SignatureError {
+    /// the value was not of the form `algorithm=hexdigest`
+    MissingSeparator,
+    /// the algorithm is not one github signs deliveries with
+    UnknownAlgorithm(String),
+    /// the digest was not valid hex
+    InvalidHex,
+    /// the digest's length did not match its algorithm
+    InvalidLength { expected: usize, found: usize },
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SignatureError::MissingSeparator => write!(f, "expected algorithm=hexdigest"),
+            SignatureError::UnknownAlgorithm(ref name) => {
+                write!(f, "unknown signature algorithm {:?}", name)
+            }
+            SignatureError::InvalidHex => write!(f, "invalid hex digest"),
+            SignatureError::InvalidLength { expected, found } => write!(
+                f,
+                "expected a digest of {} bytes but found {}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl Error for SignatureError {}
+
+/// A parsed signature header value, i.e. `sha256=4a5b...`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    algorithm: Algorithm,
+    digest: Vec<u8>,
+}
+
+impl Signature {
+    /// parses a signature header value
+    pub fn parse(value: &str) -> Result<Signature, SignatureError> {
+        let separator = value.find('=').ok_or(SignatureError::MissingSeparator)?;
+        let (name, hex) = (&value[..separator], &value[separator + 1..]);
+        let algorithm = Algorithm::from_name(name)
+            .ok_or_else(|| SignatureError::UnknownAlgorithm(name.to_owned()))?;
+        let digest = Vec::from_hex(hex).map_err(|_| SignatureError::InvalidHex)?;
+        if digest.len() != algorithm.digest_len() {
+            return Err(SignatureError::InvalidLength {
+                expected: algorithm.digest_len(),
+                found: digest.len(),
+            });
+        }
+        Ok(Signature { algorithm, digest })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+}
+
+impl FromStr for Signature {
+    type Err = SignatureError;
+
+    fn from_str(value: &str) -> Result<Signature, SignatureError> {
+        Signature::parse(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex::ToHex;
+    use proptest::prelude::*;
+
+    #[test]
+    fn parse_signatures() {
+        let signature = Signature::parse("sha1=0123456789abcdef0123456789abcdef01234567").unwrap();
+        assert_eq!(Algorithm::Sha1, signature.algorithm());
+        assert_eq!(20, signature.digest().len());
+        assert_eq!(
+            Err(SignatureError::MissingSeparator),
+            Signature::parse("sha1")
+        );
+        assert_eq!(
+            Err(SignatureError::UnknownAlgorithm("md5".to_owned())),
+            Signature::parse("md5=00")
+        );
+        assert_eq!(
+            Err(SignatureError::InvalidHex),
+            Signature::parse("sha256=zz")
+        );
+        assert_eq!(
+            Err(SignatureError::InvalidLength {
+                expected: 32,
+                found: 1
+            }),
+            Signature::parse("sha256=00")
+        );
+    }
+
+    #[test]
+    fn parse_short_and_non_ascii_values() {
+        for value in &[
+            "",
+            "=",
+            "sha",
+            "sha1=",
+            "sha1=0",
+            "é=é",
+            "sha1=é",
+            "sha256=\u{0}",
+        ] {
+            assert!(Signature::parse(value).is_err())
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn parse_never_panics(value in ".*") {
+            let _ = Signature::parse(&value);
+        }
+
+        #[test]
+        fn parse_prefixed_garbage(prefix in "(sha1|sha256)=", rest in "\\PC*") {
+            let _ = Signature::parse(&format!("{}{}", prefix, rest));
+        }
+
+        #[test]
+        fn parse_valid_signatures(sha256 in any::<bool>(), digest in any::<[u8; 32]>()) {
+            let algorithm = if sha256 { Algorithm::Sha256 } else { Algorithm::Sha1 };
+            let digest = &digest[..algorithm.digest_len()];
+            let mut hex = String::new();
+            digest.write_hex(&mut hex).unwrap();
+            let signature = Signature::parse(&format!("{}={}", algorithm, hex)).unwrap();
+            prop_assert_eq!(algorithm, signature.algorithm());
+            prop_assert_eq!(digest, signature.digest());
+        }
+    }
+}
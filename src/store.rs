@@ -0,0 +1,421 @@
+//! Stores of previously seen delivery ids, used to skip replayed
+//! and redelivered deliveries
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Records the ids of deliveries a hub has dispatched
+///
+/// Hubs record delivery ids as `DeliveryId` displays them, which is as
+/// printable ascii without whitespace
+pub trait DeliveryStore: Send + Sync {
+    /// records a delivery id, returning false when it was already
+    /// recorded and has not yet been forgotten
+    fn record(&self, id: &str) -> bool;
+
+    /// forgets a recorded delivery id, i.e. of a delivery hooks failed
+    /// to handle so that github's redelivery of it is dispatched again
+    fn forget(&self, id: &str);
+}
+
+/// An in-memory store which remembers up to `capacity` delivery ids,
+/// forgetting the least recently seen first, and optionally forgets
+/// ids after a time to live
+pub struct MemoryStore {
+    capacity: usize,
+    ttl: Option<Duration>,
+    seen: Mutex<Seen>,
+}
+
+#[derive(Default)]
+struct Seen {
+    // id -> when it was last seen
+    ids: HashMap<String, Instant>,
+    // ids in the order they were seen, entries superseded by a more
+    // recent sighting are skipped when popped
+    order: VecDeque<(String, Instant)>,
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize) -> MemoryStore {
+        MemoryStore {
+            capacity,
+            ttl: None,
+            seen: Mutex::new(Seen::default()),
+        }
+    }
+
+    /// forgets delivery ids once they were last seen longer ago than `ttl`
+    pub fn ttl(self, ttl: Duration) -> MemoryStore {
+        MemoryStore {
+            ttl: Some(ttl),
+            ..self
+        }
+    }
+}
+
+impl Seen {
+    // drops entries superseded by a more recent sighting
+    fn compact(&mut self) {
+        let ids = &self.ids;
+        self.order.retain(|&(ref id, at)| ids.get(id) == Some(&at))
+    }
+
+    fn pop(&mut self) {
+        if let Some((id, at)) = self.order.pop_front() {
+            if self.ids.get(&id) == Some(&at) {
+                self.ids.remove(&id);
+            }
+        }
+    }
+}
+
+impl DeliveryStore for MemoryStore {
+    fn record(&self, id: &str) -> bool {
+        let now = Instant::now();
+        let mut seen = match self.seen.lock() {
+            Ok(seen) => seen,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(ttl) = self.ttl {
+            while seen
+                .order
+                .front()
+                .map(|&(_, at)| now.duration_since(at) >= ttl)
+                .unwrap_or(false)
+            {
+                seen.pop()
+            }
+        }
+        let fresh = seen.ids.insert(id.to_owned(), now).is_none();
+        seen.order.push_back((id.to_owned(), now));
+        while seen.ids.len() > self.capacity {
+            seen.pop()
+        }
+        if seen.order.len() > 2 * self.capacity {
+            seen.compact()
+        }
+        fresh
+    }
+
+    fn forget(&self, id: &str) {
+        let mut seen = match self.seen.lock() {
+            Ok(seen) => seen,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        // its entry in `order` is skipped once popped
+        seen.ids.remove(id);
+    }
+}
+
+/// the number of lines a `FileStore`'s file may grow to by default
+/// before it is compacted, unless it remembers more than half as many ids
+const DEFAULT_COMPACT_AFTER: usize = 1024;
+
+/// A store which persists delivery ids to an append-only file so that
+/// they are remembered across restarts
+///
+/// Each line of the file holds the unix time a delivery was first seen
+/// and its id, or `-` and the id of a delivery which was forgotten, so
+/// ids recorded may not hold line breaks. Ids are remembered for the
+/// time to live after they were first seen, recording them again does
+/// not extend it. Expired and forgotten entries are dropped when the
+/// store is opened, and once most lines of the file are such entries.
+pub struct FileStore {
+    path: PathBuf,
+    ttl: Option<Duration>,
+    compact_after: usize,
+    state: Mutex<Persisted>,
+}
+
+struct Persisted {
+    // id -> unix time it was first seen
+    ids: HashMap<String, u64>,
+    file: File,
+    // lines written to the file since it was last compacted
+    lines: usize,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// whether an id first seen at `at` is still remembered at `now`. Ids
+/// whose expiry overflows, i.e. of corrupt lines, are expired
+fn live(at: u64, ttl: Option<Duration>, now: u64) -> bool {
+    match ttl {
+        Some(ttl) => at
+            .checked_add(ttl.as_secs())
+            .map(|expires| expires > now)
+            .unwrap_or(false),
+        None => true,
+    }
+}
+
+/// a path next to `path` no other store, process or file uses
+fn tmp_path(path: &Path) -> PathBuf {
+    static COMPACTIONS: AtomicUsize = AtomicUsize::new(0);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        name,
+        process::id(),
+        COMPACTIONS.fetch_add(1, Ordering::SeqCst)
+    ))
+}
+
+impl FileStore {
+    /// opens or creates a store at `path`, forgetting ids after `ttl`, if any
+    pub fn open<P>(path: P, ttl: Option<Duration>) -> io::Result<FileStore>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        let now = unix_now();
+        let mut ids = HashMap::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                let mut parts = line.splitn(2, ' ');
+                match (parts.next(), parts.next()) {
+                    (Some("-"), Some(id)) => {
+                        ids.remove(id);
+                    }
+                    (Some(at), Some(id)) => {
+                        if let Ok(at) = at.parse::<u64>() {
+                            if live(at, ttl, now) {
+                                ids.insert(id.to_owned(), at);
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        let file = compact(&path, &ids)?;
+        Ok(FileStore {
+            path,
+            ttl,
+            compact_after: DEFAULT_COMPACT_AFTER,
+            state: Mutex::new(Persisted {
+                lines: ids.len(),
+                ids,
+                file,
+            }),
+        })
+    }
+
+    /// compacts the file once it holds more than `lines` lines and more
+    /// than twice as many as ids remembered, 1024 by default
+    pub fn compact_after(self, lines: usize) -> FileStore {
+        FileStore {
+            compact_after: lines,
+            ..self
+        }
+    }
+
+    // drops expired and forgotten entries from the file once they make up most of it
+    fn maintain(&self, state: &mut Persisted, now: u64) {
+        if state.lines <= self.compact_after.max(2 * state.ids.len()) {
+            return;
+        }
+        let ttl = self.ttl;
+        state.ids.retain(|_, &mut at| live(at, ttl, now));
+        match compact(&self.path, &state.ids) {
+            Ok(file) => {
+                state.file = file;
+                state.lines = state.ids.len();
+            }
+            Err(e) => error!("failed to compact delivery ids in {:?}: {}", self.path, e),
+        }
+    }
+
+    /// the path of the file backing this store
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// rewrites the file at `path` to hold only `ids`, returning it opened for appending
+fn compact(path: &Path, ids: &HashMap<String, u64>) -> io::Result<File> {
+    let tmp = tmp_path(path);
+    {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+        for (id, at) in ids {
+            writeln!(file, "{} {}", at, id)?;
+        }
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)?;
+    OpenOptions::new().append(true).open(path)
+}
+
+impl DeliveryStore for FileStore {
+    fn record(&self, id: &str) -> bool {
+        let now = unix_now();
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let seen = state
+            .ids
+            .get(id)
+            .map(|&at| live(at, self.ttl, now))
+            .unwrap_or(false);
+        if seen {
+            return false;
+        }
+        state.ids.insert(id.to_owned(), now);
+        match writeln!(state.file, "{} {}", now, id) {
+            Ok(()) => state.lines += 1,
+            Err(e) => error!("failed to persist delivery id {:?}: {}", id, e),
+        }
+        self.maintain(&mut state, now);
+        true
+    }
+
+    fn forget(&self, id: &str) {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if state.ids.remove(id).is_some() {
+            match writeln!(state.file, "- {}", id) {
+                Ok(()) => state.lines += 1,
+                Err(e) => error!("failed to persist forgetting delivery id {:?}: {}", id, e),
+            }
+            self.maintain(&mut state, unix_now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::thread;
+
+    #[test]
+    fn memory_store_records_duplicates() {
+        let store = MemoryStore::new(10);
+        assert!(store.record("a"));
+        assert!(store.record("b"));
+        assert!(!store.record("a"));
+        store.forget("a");
+        assert!(store.record("a"));
+    }
+
+    #[test]
+    fn memory_store_forgets_least_recently_seen() {
+        let store = MemoryStore::new(2);
+        assert!(store.record("a"));
+        assert!(store.record("b"));
+        assert!(!store.record("a"));
+        assert!(store.record("c"));
+        // b was seen least recently
+        assert!(store.record("b"));
+        assert!(!store.record("c"));
+    }
+
+    #[test]
+    fn memory_store_forgets_expired() {
+        let store = MemoryStore::new(10).ttl(Duration::from_millis(10));
+        assert!(store.record("a"));
+        thread::sleep(Duration::from_millis(20));
+        assert!(store.record("a"));
+    }
+
+    #[test]
+    fn file_store_persists() {
+        let path = env::temp_dir().join(format!("afterparty-deliveries-{}", ::std::process::id()));
+        {
+            let store = FileStore::open(&path, None).unwrap();
+            assert!(store.record("a"));
+            assert!(!store.record("a"));
+            assert!(store.record("b c"));
+        }
+        let store = FileStore::open(&path, None).unwrap();
+        assert!(!store.record("a"));
+        assert!(!store.record("b c"));
+        assert!(store.record("b"));
+        store.forget("a");
+        drop(store);
+        let store = FileStore::open(&path, None).unwrap();
+        assert!(store.record("a"));
+        assert!(!store.record("b"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_store_keeps_first_sighting() {
+        let path = env::temp_dir().join(format!("afterparty-sightings-{}", ::std::process::id()));
+        let store = FileStore::open(&path, Some(Duration::from_secs(60))).unwrap();
+        let first = unix_now() - 59;
+        store
+            .state
+            .lock()
+            .unwrap()
+            .ids
+            .insert("a".to_owned(), first);
+        assert!(!store.record("a"));
+        assert_eq!(Some(&first), store.state.lock().unwrap().ids.get("a"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_store_compacts() {
+        let path = env::temp_dir().join(format!("afterparty-compacted-{}", ::std::process::id()));
+        let store = FileStore::open(&path, None).unwrap().compact_after(4);
+        let lines = || fs::read_to_string(&path).unwrap().lines().count();
+        for id in &["a", "b", "c"] {
+            assert!(store.record(id));
+            store.forget(id);
+        }
+        // compacted to `c` once its line was the 5th, then forgotten again
+        assert_eq!(2, lines());
+        assert!(store.record("d"));
+        assert!(store.record("e"));
+        assert!(store.record("f"));
+        // not compacted while half of the lines are live
+        assert_eq!(5, lines());
+        drop(store);
+        let store = FileStore::open(&path, None).unwrap();
+        assert!(store.record("a"));
+        assert!(!store.record("d"));
+        assert!(!store.record("e"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_store_expires_corrupt_lines() {
+        let dir = env::temp_dir().join(format!("afterparty-store-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("deliveries");
+        let sibling = dir.join("deliveries.tmp");
+        fs::write(&sibling, "not ours").unwrap();
+        fs::write(
+            &path,
+            format!("{} a\nyesterday b\n{} c\n", u64::MAX, unix_now()),
+        )
+        .unwrap();
+        let store = FileStore::open(&path, Some(Duration::from_secs(60))).unwrap();
+        assert!(store.record("a"));
+        assert!(store.record("b"));
+        assert!(!store.record("c"));
+        assert_eq!("not ours", fs::read_to_string(&sibling).unwrap());
+        assert_eq!(2, fs::read_dir(&dir).unwrap().count());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
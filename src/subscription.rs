@@ -0,0 +1,391 @@
+//! Patterns hooks subscribe to events with
+//!
+//! A pattern names an event, i.e. `push`, or a glob of events, i.e.
+//! `pull_request*`, optionally followed by `.` and `|` separated actions,
+//! i.e. `issues.labeled|unlabeled`. Globs are compiled once when a hook
+//! is registered so that matching deliveries stays cheap
+
+use hook::Registered;
+use std::collections::HashMap;
+use std::fmt;
+
+/// The events, and optionally actions, a hook is interested in
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subscription {
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Pattern {
+    source: String,
+    event: EventPattern,
+    actions: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum EventPattern {
+    Exact(String),
+    // the literal parts of a glob between its `*`s
+    Glob(Vec<String>),
+}
+
+impl EventPattern {
+    fn parse(event: &str) -> EventPattern {
+        if event.contains('*') {
+            EventPattern::Glob(event.split('*').map(|part| part.to_owned()).collect())
+        } else {
+            EventPattern::Exact(event.to_owned())
+        }
+    }
+
+    fn matches(&self, event: &str) -> bool {
+        match self {
+            EventPattern::Exact(exact) => exact == event,
+            EventPattern::Glob(parts) => {
+                let (first, last) = (&parts[0], &parts[parts.len() - 1]);
+                if event.len() < first.len() + last.len()
+                    || !event.starts_with(first.as_str())
+                    || !event.ends_with(last.as_str())
+                {
+                    return false;
+                }
+                let mut rest = &event[first.len()..event.len() - last.len()];
+                for part in &parts[1..parts.len() - 1] {
+                    match rest.find(part.as_str()) {
+                        Some(at) => rest = &rest[at + part.len()..],
+                        None => return false,
+                    }
+                }
+                true
+            }
+        }
+    }
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Pattern {
+        let (event, actions) = match pattern.find('.') {
+            Some(dot) => (
+                &pattern[..dot],
+                Some(
+                    pattern[dot + 1..]
+                        .split('|')
+                        .map(|action| action.to_owned())
+                        .collect::<Vec<_>>(),
+                ),
+            ),
+            None => (pattern, None),
+        };
+        // such patterns would silently never match
+        if event.is_empty() || actions.iter().flatten().any(|action| action.is_empty()) {
+            panic!("invalid subscription `{}`: empty event or action", pattern)
+        }
+        Pattern {
+            source: pattern.to_owned(),
+            event: EventPattern::parse(event),
+            actions,
+        }
+    }
+
+    fn accepts(&self, action: Option<&str>) -> bool {
+        match (&self.actions, action) {
+            (None, _) => true,
+            (Some(actions), Some(action)) => actions.iter().any(|a| a == action),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+impl Subscription {
+    /// subscribes to deliveries matching any of the given patterns
+    ///
+    /// # Panics
+    ///
+    /// when a pattern's event or one of its actions is empty, i.e. `push.`
+    pub fn new<I, P>(patterns: I) -> Subscription
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<str>,
+    {
+        Subscription {
+            patterns: patterns
+                .into_iter()
+                .map(|pattern| Pattern::parse(pattern.as_ref()))
+                .collect(),
+        }
+    }
+
+    /// whether deliveries of an event may be of interest,
+    /// regardless of their action
+    pub fn matches_event(&self, event: &str) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.event.matches(event))
+    }
+
+    /// whether deliveries of an event are only of interest for some actions
+    pub fn filters_actions(&self, event: &str) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.actions.is_some() && pattern.event.matches(event))
+    }
+
+    /// whether deliveries of an event with the given action are of interest
+    pub fn matches(&self, event: &str, action: Option<&str>) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.event.matches(event) && pattern.accepts(action))
+    }
+
+    // the event part of each pattern, hooks registered without a name
+    // are numbered among the hooks subscribed to the same events
+    fn events(&self) -> String {
+        self.patterns
+            .iter()
+            .map(|pattern| pattern.source.split('.').next().unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl fmt::Display for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let patterns = self
+            .patterns
+            .iter()
+            .map(|pattern| pattern.source.as_str())
+            .collect::<Vec<_>>();
+        f.write_str(&patterns.join(","))
+    }
+}
+
+impl<'a> From<&'a str> for Subscription {
+    fn from(pattern: &'a str) -> Subscription {
+        Subscription::new(vec![pattern])
+    }
+}
+
+impl From<String> for Subscription {
+    fn from(pattern: String) -> Subscription {
+        Subscription::new(vec![pattern])
+    }
+}
+
+impl<'a, P: AsRef<str>> From<&'a [P]> for Subscription {
+    fn from(patterns: &'a [P]) -> Subscription {
+        Subscription::new(patterns)
+    }
+}
+
+impl<P: AsRef<str>> From<Vec<P>> for Subscription {
+    fn from(patterns: Vec<P>) -> Subscription {
+        Subscription::new(patterns)
+    }
+}
+
+// arrays of up to 16 patterns
+macro_rules! array_subscriptions {
+    ($($len:expr)*) => {
+        $(
+            impl<P: AsRef<str>> From<[P; $len]> for Subscription {
+                fn from(patterns: [P; $len]) -> Subscription {
+                    Subscription::new(&patterns[..])
+                }
+            }
+        )*
+    };
+}
+
+array_subscriptions!(1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16);
+
+/// Hooks indexed by the events they subscribed to
+pub(crate) struct Hooks<H: ?Sized> {
+    registered: Vec<Registered<H>>,
+    // event name -> hooks subscribed to it by name
+    exact: HashMap<String, Vec<usize>>,
+    // hooks subscribed to globs of events
+    globs: Vec<usize>,
+}
+
+impl<H: ?Sized> Default for Hooks<H> {
+    fn default() -> Hooks<H> {
+        Hooks {
+            registered: vec![],
+            exact: HashMap::new(),
+            globs: vec![],
+        }
+    }
+}
+
+impl<H: ?Sized> Hooks<H> {
+    /// registers a hook, naming it after its subscription and its position
+    /// among hooks subscribed to the same events when no name is given,
+    /// i.e. `push#0`
+    pub(crate) fn register(
+        &mut self,
+        subscription: Subscription,
+        name: Option<&str>,
+        required: bool,
+        hook: Box<H>,
+    ) {
+        let index = self.registered.len();
+        let name = name.map(|name| name.to_owned()).unwrap_or_else(|| {
+            let events = subscription.events();
+            let position = self
+                .registered
+                .iter()
+                .filter(|hook| hook.subscription().events() == events)
+                .count();
+            format!("{}#{}", subscription, position)
+        });
+        let mut globbed = false;
+        for pattern in &subscription.patterns {
+            match pattern.event {
+                EventPattern::Exact(ref event) => {
+                    let hooks = self.exact.entry(event.clone()).or_insert(vec![]);
+                    if hooks.last() != Some(&index) {
+                        hooks.push(index)
+                    }
+                }
+                EventPattern::Glob(_) => globbed = true,
+            }
+        }
+        if globbed {
+            self.globs.push(index)
+        }
+        self.registered
+            .push(Registered::new(name, required, subscription, hook))
+    }
+
+    /// hooks which may be interested in deliveries of an event,
+    /// in the order they were registered
+    pub(crate) fn interested(&self, event: &str) -> Option<Vec<&Registered<H>>> {
+        let exact = self.exact.get(event).map(|hooks| &hooks[..]).unwrap_or(&[]);
+        let globbed = self
+            .globs
+            .iter()
+            .filter(|index| !exact.contains(index))
+            .filter(|&&index| self.registered[index].subscription().matches_event(event));
+        let mut indices = exact.iter().chain(globbed).cloned().collect::<Vec<_>>();
+        indices.sort_unstable();
+        let hooks = indices
+            .into_iter()
+            .map(|index| &self.registered[index])
+            .collect::<Vec<_>>();
+        if hooks.is_empty() {
+            None
+        } else {
+            Some(hooks)
+        }
+    }
+
+    /// hooks interested in a delivery of an event, looking up the
+    /// delivery's action only when some hook subscribed to actions
+    pub(crate) fn subscribed<F, E>(
+        &self,
+        event: &str,
+        action: F,
+    ) -> Result<Option<Vec<&Registered<H>>>, E>
+    where
+        F: FnOnce() -> Result<Option<String>, E>,
+    {
+        let hooks = match self.interested(event) {
+            Some(hooks) => hooks,
+            None => return Ok(None),
+        };
+        let action = if hooks
+            .iter()
+            .any(|hook| hook.subscription().filters_actions(event))
+        {
+            action()?
+        } else {
+            None
+        };
+        let hooks = hooks
+            .into_iter()
+            .filter(|hook| hook.subscription().matches(event, action.as_deref()))
+            .collect::<Vec<_>>();
+        if hooks.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(hooks))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_patterns() {
+        let subscription = Subscription::from("pull_request*");
+        assert!(subscription.matches_event("pull_request"));
+        assert!(subscription.matches_event("pull_request_review"));
+        assert!(!subscription.matches_event("push"));
+        let subscription = Subscription::from("*_comment");
+        assert!(subscription.matches_event("issue_comment"));
+        assert!(!subscription.matches_event("issues"));
+        let subscription = Subscription::from("p*_*w");
+        assert!(subscription.matches_event("pull_request_review"));
+        assert!(!subscription.matches_event("pull_request"));
+        assert!(Subscription::from("*").matches_event("push"));
+        let subscription = Subscription::from(["push", "issues.labeled|unlabeled"]);
+        assert!(subscription.matches("push", None));
+        assert!(subscription.matches("issues", Some("unlabeled")));
+        assert!(!subscription.matches("issues", Some("opened")));
+        assert!(!subscription.matches("issues", None));
+        assert!(subscription.filters_actions("issues"));
+        assert!(!subscription.filters_actions("push"));
+    }
+
+    #[test]
+    fn index_hooks() {
+        let mut hooks = Hooks::<str>::default();
+        hooks.register("*".into(), None, true, "any".into());
+        hooks.register(["push", "pu*"][..].into(), None, true, "push".into());
+        hooks.register(
+            "project_*.created".into(),
+            Some("projects"),
+            true,
+            "projects".into(),
+        );
+        hooks.register("pull_request".into(), None, true, "pull_request".into());
+        let names = |hooks: Option<Vec<&Registered<str>>>| {
+            hooks
+                .unwrap_or_default()
+                .into_iter()
+                .map(|hook| hook.name().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["*#0", "push,pu*#0"], names(hooks.interested("push")));
+        assert_eq!(
+            vec!["*#0", "push,pu*#0", "pull_request#0"],
+            names(hooks.interested("pull_request"))
+        );
+        assert_eq!(
+            vec!["*#0", "projects"],
+            names(hooks.interested("project_card"))
+        );
+        assert_eq!(
+            vec!["*#0"],
+            names(
+                hooks
+                    .subscribed("project_card", || Ok::<_, ()>(Some("moved".to_owned())))
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            Err("unreadable"),
+            hooks
+                .subscribed("project_card", || Err("unreadable"))
+                .map(|hooks| hooks.is_some())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid subscription `push.`")]
+    fn reject_empty_actions() {
+        let _ = Subscription::from("push.");
+    }
+}
@@ -0,0 +1,261 @@
+//! Verification of delivery signatures
+
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::mac::MacResult;
+use crypto::sha1::Sha1;
+use crypto::sha2::Sha256;
+use secrets::{SecretContext, SecretProvider, Secrets};
+use signature::{Algorithm, Signature, SignatureError};
+use std::error::Error;
+use std::fmt;
+use std::time::SystemTime;
+
+/// Reasons a delivery may fail verification
+#[derive(Debug, PartialEq)]
+pub enum VerifyError {
+    /// the delivery carried neither a `X-Hub-Signature` nor a `X-Hub-Signature-256` header
+    MissingSignature,
+    /// the delivery was only signed with sha1 while sha256 is required
+    Sha256Required,
+    /// the signature header could not be parsed
+    Malformed(SignatureError),
+    /// the signature header carried a digest of another algorithm than expected
+    UnexpectedAlgorithm(Algorithm),
+    /// the payload's repository does not belong to the organization it names
+    ConflictingScopes(SecretContext),
+    /// no secrets were provided for the delivery
+    NoSecrets(SecretContext),
+    /// the signature did not match any live secret
+    Mismatch,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VerifyError::MissingSignature => write!(f, "missing signature"),
+            VerifyError::Sha256Required => write!(f, "sha256 signature required"),
+            VerifyError::Malformed(ref e) => write!(f, "malformed signature: {}", e),
+            VerifyError::UnexpectedAlgorithm(algorithm) => {
+                write!(f, "unexpected {} signature", algorithm)
+            }
+            VerifyError::ConflictingScopes(ref context) => {
+                write!(f, "conflicting scopes in {:?}", context)
+            }
+            VerifyError::NoSecrets(ref context) => {
+                write!(f, "no secrets provided for {:?}", context)
+            }
+            VerifyError::Mismatch => write!(f, "signature mismatch"),
+        }
+    }
+}
+
+impl Error for VerifyError {}
+
+/// Verifies delivery signatures against the secrets provided for them
+pub struct Verifier {
+    provider: Box<dyn SecretProvider>,
+    sha256_required: bool,
+}
+
+impl Verifier {
+    pub fn new<S>(secrets: S) -> Verifier
+    where
+        S: Into<Secrets>,
+    {
+        Verifier::with_provider(secrets.into())
+    }
+
+    /// verifies deliveries with secrets looked up by a provider,
+    /// i.e. per repository, organization or installation
+    pub fn with_provider<P>(provider: P) -> Verifier
+    where
+        P: SecretProvider + 'static,
+    {
+        Verifier {
+            provider: Box::new(provider),
+            sha256_required: false,
+        }
+    }
+
+    /// when enabled, deliveries which only carry the legacy sha1
+    /// `X-Hub-Signature` header will be rejected
+    pub fn require_sha256(self, required: bool) -> Verifier {
+        Verifier {
+            sha256_required: required,
+            ..self
+        }
+    }
+
+    /// returns the name of the secret which signed the payload. Payloads
+    /// must be signed with a live secret of the most specific scope which
+    /// has secrets
+    pub fn verify(
+        &self,
+        payload: &str,
+        signature: Option<&str>,
+        signature_256: Option<&str>,
+    ) -> Result<String, VerifyError> {
+        // https://developer.github.com/webhooks/securing/#validating-payloads-from-github
+        let (header, expected) = match (signature_256, signature) {
+            (Some(sig), _) => (sig, Algorithm::Sha256),
+            (None, Some(_)) if self.sha256_required => return Err(VerifyError::Sha256Required),
+            (None, Some(sig)) => (sig, Algorithm::Sha1),
+            (None, None) => return Err(VerifyError::MissingSignature),
+        };
+        let signature = Signature::parse(header).map_err(VerifyError::Malformed)?;
+        if signature.algorithm() != expected {
+            return Err(VerifyError::UnexpectedAlgorithm(signature.algorithm()));
+        }
+        let context = SecretContext::from_payload(payload);
+        if !context.is_consistent() {
+            return Err(VerifyError::ConflictingScopes(context));
+        }
+        let secrets = match self.provider.secrets(&context).into_iter().next() {
+            Some(secrets) => secrets,
+            None => return Err(VerifyError::NoSecrets(context)),
+        };
+        let name = secrets
+            .live(SystemTime::now())
+            .find(|secret| matches(&signature, secret.value(), payload))
+            .map(|secret| secret.name().to_owned());
+        name.ok_or(VerifyError::Mismatch)
+    }
+}
+
+fn matches(signature: &Signature, secret: &str, payload: &str) -> bool {
+    match signature.algorithm() {
+        Algorithm::Sha1 => hmac(Sha1::new(), signature, secret, payload),
+        Algorithm::Sha256 => hmac(Sha256::new(), signature, secret, payload),
+    }
+}
+
+fn hmac<D: Digest>(digest: D, signature: &Signature, secret: &str, payload: &str) -> bool {
+    let mut mac = Hmac::new(digest, secret.as_bytes());
+    mac.input(payload.as_bytes());
+    // constant time comparison
+    mac.result() == MacResult::new(signature.digest())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex::ToHex;
+
+    fn sign(secret: &str, payload: &str) -> String {
+        let mut mac = Hmac::new(Sha256::new(), secret.as_bytes());
+        mac.input(payload.as_bytes());
+        let mut signature = String::new();
+        mac.result().code().write_hex(&mut signature).unwrap();
+        format!("sha256={}", signature)
+    }
+
+    #[test]
+    fn verify_errors() {
+        let verifier = Verifier::new("secret").require_sha256(true);
+        let payload = r#"{"zen": "Approachable is better than simple."}"#;
+        assert_eq!(
+            Err(VerifyError::MissingSignature),
+            verifier.verify(payload, None, None)
+        );
+        assert_eq!(
+            Err(VerifyError::Sha256Required),
+            verifier.verify(payload, Some("sha1=00"), None)
+        );
+        assert_eq!(
+            Err(VerifyError::Malformed(SignatureError::MissingSeparator)),
+            verifier.verify(payload, None, Some("sha"))
+        );
+        assert_eq!(
+            Err(VerifyError::UnexpectedAlgorithm(Algorithm::Sha1)),
+            verifier.verify(
+                payload,
+                None,
+                Some("sha1=0123456789abcdef0123456789abcdef01234567")
+            )
+        );
+        assert_eq!(
+            Err(VerifyError::Mismatch),
+            verifier.verify(payload, None, Some(sign("other", payload).as_ref()))
+        );
+        assert_eq!(
+            Ok("default".to_owned()),
+            verifier.verify(payload, None, Some(sign("secret", payload).as_ref()))
+        )
+    }
+
+    #[test]
+    fn verify_most_specific_scope() {
+        use secrets::{MemorySecrets, SecretScope};
+        let verifier = Verifier::with_provider(
+            MemorySecrets::new()
+                .with(SecretScope::Repository("victim/api".to_owned()), "api")
+                .with(SecretScope::Organization("victim".to_owned()), "victim")
+                .with(SecretScope::Organization("attacker".to_owned()), "attacker")
+                .with(SecretScope::Installation(42), "installation"),
+        );
+        let spoofed = r#"{
+          "repository": { "full_name": "victim/app" },
+          "organization": { "login": "attacker" }
+        }"#;
+        match verifier.verify(spoofed, None, Some(sign("attacker", spoofed).as_ref())) {
+            Err(VerifyError::ConflictingScopes(_)) => (),
+            other => panic!("expected conflicting scopes, got {:?}", other),
+        }
+        // an installation may not vouch for a repository without an organization
+        let spoofed = r#"{
+          "repository": { "full_name": "victim/app" },
+          "installation": { "id": 42 }
+        }"#;
+        assert_eq!(
+            Err(VerifyError::Mismatch),
+            verifier.verify(spoofed, None, Some(sign("installation", spoofed).as_ref()))
+        );
+        let spoofed = r#"{
+          "repository": { "full_name": "unknown/app" },
+          "installation": { "id": 42 }
+        }"#;
+        match verifier.verify(spoofed, None, Some(sign("installation", spoofed).as_ref())) {
+            Err(VerifyError::NoSecrets(_)) => (),
+            other => panic!("expected no secrets, got {:?}", other),
+        }
+        // organizations and apps installed in them need no secret in common
+        let payload = r#"{
+          "repository": { "full_name": "victim/app" },
+          "organization": { "login": "victim" },
+          "installation": { "id": 42 }
+        }"#;
+        assert_eq!(
+            Ok("default".to_owned()),
+            verifier.verify(payload, None, Some(sign("victim", payload).as_ref()))
+        );
+        let payload = r#"{"installation": { "id": 42 }}"#;
+        assert_eq!(
+            Ok("default".to_owned()),
+            verifier.verify(payload, None, Some(sign("installation", payload).as_ref()))
+        );
+        let payload = r#"{
+          "repository": { "full_name": "victim/api" },
+          "organization": { "login": "victim" }
+        }"#;
+        assert_eq!(
+            Ok("default".to_owned()),
+            verifier.verify(payload, None, Some(sign("api", payload).as_ref()))
+        );
+        assert_eq!(
+            Err(VerifyError::Mismatch),
+            verifier.verify(payload, None, Some(sign("victim", payload).as_ref()))
+        );
+    }
+
+    #[test]
+    fn verify_without_secrets() {
+        let verifier = Verifier::with_provider(::secrets::MemorySecrets::new());
+        let payload = r#"{"zen": "Approachable is better than simple."}"#;
+        assert_eq!(
+            Err(VerifyError::NoSecrets(SecretContext::default())),
+            verifier.verify(payload, None, Some(sign("secret", payload).as_ref()))
+        )
+    }
+}